@@ -44,7 +44,11 @@ pub fn parseArgs() -> bool {
         return false;
     }
     let mut boardPosition : crate::board::BoardState = crate::board::BoardState::new();
-    let mut depth = 6;
+    let mut limits = crate::engine::limits::SearchLimits::default();
+    let mut perftDepth : Option<u32> = None;
+    let mut hashSize = crate::engine::transposition::DEFAULT_HASH_MB;
+    let mut suitePath : Option<String> = None;
+    let mut fenGiven = false;
     for i in 1..args.len() {
         let arg = Argument::new(&args[i]);
         if arg.key.is_none() {
@@ -52,8 +56,17 @@ pub fn parseArgs() -> bool {
         }
         match arg.key.as_ref().unwrap().as_str() {
             "fen" => {
-                let fenstr = arg.value.unwrap();
-                boardPosition = crate::board::BoardState::new_from_FEN(fenstr.as_str())
+                let fenstr = arg.value.unwrap_or_default();
+                match crate::board::BoardState::try_from_fen(fenstr.as_str()) {
+                    Ok(newPosition) => {
+                        boardPosition = newPosition;
+                        fenGiven = true;
+                    }
+                    Err(err) => {
+                        say!("{}\n",err);
+                        return false;
+                    }
+                }
             }
             "depth" => {
                 let depthstr = arg.value.unwrap();
@@ -62,7 +75,62 @@ pub fn parseArgs() -> bool {
                     say!("Invalid depth {}",depthstr);
                     return false;
                 }
-                depth = depthResult.ok().unwrap();
+                limits.depth = depthResult.ok();
+            }
+            "perft" => {
+                let depthstr = arg.value.unwrap_or_default();
+                let depthResult = depthstr.parse::<u32>();
+                if depthResult.is_err() {
+                    say!("Invalid perft depth {}",depthstr);
+                    return false;
+                }
+                perftDepth = depthResult.ok();
+            }
+            "movetime" => {
+                let timestr = arg.value.unwrap_or_default();
+                let timeResult = timestr.parse::<u64>();
+                if timeResult.is_err() {
+                    say!("Invalid move time {}",timestr);
+                    return false;
+                }
+                limits.moveTime = Some(std::time::Duration::from_millis(timeResult.ok().unwrap()));
+            }
+            "nodes" => {
+                let nodestr = arg.value.unwrap_or_default();
+                let nodeResult = nodestr.parse::<u64>();
+                if nodeResult.is_err() {
+                    say!("Invalid node count {}",nodestr);
+                    return false;
+                }
+                limits.nodes = nodeResult.ok();
+            }
+            "hash" => {
+                let hashstr = arg.value.unwrap_or_default();
+                let Some(megabytes) = crate::engine::transposition::parseHashSize(&hashstr) else {
+                    say!("Invalid hash size {} - must be from {} to {} MB",hashstr,crate::engine::transposition::MIN_HASH_MB,crate::engine::transposition::MAX_HASH_MB);
+                    return false;
+                };
+                hashSize = megabytes;
+            }
+            "suite" => {
+                if arg.value.is_none() {
+                    say!("No suite file given - use --suite=file.epd\n");
+                    return false;
+                }
+                suitePath = arg.value;
+            }
+            "xqf2pgn" => {
+                let path = arg.value.unwrap_or_default();
+                match std::fs::read(&path).map_err(|err| err.to_string()).and_then(|bytes| crate::xqf::readXqf(&bytes).map_err(|err| err.to_string())) {
+                    Ok(xqfGame) => {
+                        say!("{}",crate::pgn::writePgn(&xqfGame.toPgn(), crate::notation::Notation::default()));
+                        return true;
+                    }
+                    Err(err) => {
+                        say!("Couldn't convert '{}': {}\n",path,err);
+                        return false;
+                    }
+                }
             }
             &_ => {
                 say!("Unknown argument {}",arg.key.as_ref().unwrap());
@@ -70,7 +138,34 @@ pub fn parseArgs() -> bool {
             }
         }
     }
-    say!("{}\n",crate::engine::Engine::evalToDepth(&boardPosition, depth));
+    if let Some(perftDepth) = perftDepth {
+        crate::runPerft(&boardPosition, perftDepth);
+        return true;
+    }
+    if limits.depth.is_none() && limits.moveTime.is_none() && limits.nodes.is_none() {
+        limits.depth = Some(6);
+    }
+    let mut engine = crate::engine::Engine::new();
+    engine.setHashSize(hashSize);
+    if let Some(suitePath) = suitePath {
+        if fenGiven { // Every suite position brings its own FEN
+            say!("--fen can't be used with --suite\n");
+            return false;
+        }
+        match std::fs::read_to_string(&suitePath).map_err(|err| err.to_string()).and_then(|text| crate::suite::readSuite(&text).map_err(|err| err.to_string())) {
+            Ok(suite) => {
+                if let Err(err) = crate::suite::runSuite(&mut engine, &suite, &limits, &mut stdout()) {
+                    say!("Couldn't write suite results: {}\n",err);
+                }
+                return true;
+            }
+            Err(err) => {
+                say!("Couldn't read suite '{}': {}\n",suitePath,err);
+                return false;
+            }
+        }
+    }
+    say!("{}\n",engine.searchVerbosely(&boardPosition, &[], &limits).score);
 
 
     return true;
@@ -2,15 +2,18 @@ use std::{char::from_digit, hint::unreachable_unchecked};
 pub mod piece;
 pub mod tile;
 pub mod packedmove;
+pub mod fen;
+pub mod zobrist;
+pub mod rules;
 mod rayiterator;
 use piece::{PieceType,Piece};
 use tile::{Tile,TileIterator,PieceIndex};
-use crate::engine::score::ScoreF32;
-use crate::engine::score;
+use crate::engine::score::ScoreI32;
 
 use self::piece::{PieceSet, PieceSetIterator};
 use self::packedmove::{PackedMove, PackedCoord,DEAD_PIECE_PACKEDCOORD};
 use self::rayiterator::RayIterator;
+use self::fen::FenError;
 
 pub type Coord = (usize,usize);
 pub type TileGrid = [[Tile;9];10];
@@ -24,8 +27,10 @@ pub struct BoardState
     pub squares : TileGrid,
     pub isRedTurn : bool,
     pub plyNumber : i16, // Zero-indexed. Either player moving increments this. Even for Red and odd for Black
+    pub halfMoveClock : u16, // Plies since the last capture, for the no-capture draw rule
     pub(crate) redPieces : PieceSet,
-    pub(crate) blackPieces : PieceSet
+    pub(crate) blackPieces : PieceSet,
+    pub(crate) zobristKey : u64 // Covers the pieces and whose move it is, and nothing else. Kept up to date by make_move.
 }
 
 
@@ -38,6 +43,17 @@ const BLACK_RIVER : usize = 5;
 /// the Y index for where red's river starts.
 const RED_RIVER : usize = 4;
 
+/// Everything make_move throws away that unmake_move needs to put the board back the way it was.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct UndoRecord {
+    pub playedMove : PackedMove,
+    pub captured : PieceIndex,
+    capturedSlot : u8, // Which slot of its PieceSet the captured piece sat in, so the sets come back exactly as they were
+    pub plyNumber : i16,
+    pub halfMoveClock : u16,
+    pub zobristKey : u64
+}
+
 pub const STARTING_POSITION_FEN : &str = "rheakaehr/9/1c5c1/p1p1p1p1p/9/9/P1P1P1P1P/1C5C1/9/RHEAKAEHR w - - 0 1";
 
 impl BoardState {
@@ -45,164 +61,143 @@ impl BoardState {
         return Self::new_from_FEN(STARTING_POSITION_FEN);
     }
     pub fn new_from_FEN(fenstr : &str) -> Self { // TODO: Find a good default argument / overloading workaround pattern for Rust
+        return Self::try_from_fen(fenstr).unwrap_or_else(|err| panic!("{}",err));
+    }
+
+    ///The non-panicking version of new_from_FEN, for when the FEN comes from somewhere we don't control.
+    pub fn try_from_fen(fenstr : &str) -> Result<Self,FenError> {
         let mut ret =  Self {
             squares : Default::default(),
             isRedTurn : true,
             plyNumber : 1,
+            halfMoveClock : 0,
             redPieces : Default::default(),
-            blackPieces : Default::default()
+            blackPieces : Default::default(),
+            zobristKey : 0
         };
 
-        ret.loadFEN(fenstr);
+        ret.loadFEN(fenstr)?;
 
-        return ret;
+        return Ok(ret);
     }
 
-    fn skipWhitespace(iterator : &mut core::str::Chars) -> Option<char>{
-        loop {
-            let cara = iterator.next();
-            if cara.is_none() {
-                return cara;
-            }
-            if !cara.unwrap().is_whitespace() {
-                return cara;
-            }
-        }
-    }
-    
-    fn readNumber(iterator : &mut core::str::Chars) -> i16{
-        let mut str : String = Default::default();
-        loop {
-            let cara = iterator.next();
-            if cara.is_none() {
-                break;
-            }
-            if cara.unwrap().is_digit(10) {
-                str.push(cara.unwrap());
+    /// Helper of a helper of a helper; for placePiece's eyes only, really.
+    fn spawnSpecificPiece(arr : &mut [PackedCoord], coord : &PackedCoord) -> bool {
+        for oldCoord in arr.iter_mut() {
+            if *oldCoord == DEAD_PIECE_PACKEDCOORD {
+                *oldCoord = *coord;
+                return true;
             }
         }
-        let ret = str.parse::<i16>();
-        return ret.unwrap_or(1);
-    }
-
-    /// Helper of a helper of a helper; for placePiece's eyes only, really.
-    fn spawnSpecificPiece<const N: usize>(arr : &mut [PackedCoord;N], coord : &PackedCoord) {
-        return BoardState::setSpecificPiece(arr, coord, &DEAD_PIECE_PACKEDCOORD);
+        return false; // Already got as many of these as we can have
     }
 
-    /// Four layers deep of helping, here. Finds the piece in this array that is that the target coordinate and sets its new value.
-    fn setSpecificPiece<const N: usize>(arr : &mut [PackedCoord;N], coord : &PackedCoord, targetCoord : &PackedCoord) {
-        for (i,oldCoord) in arr.into_iter().enumerate() {
-            if *oldCoord == *targetCoord {
-                arr[i] = *coord;
-                return;
-            }
-        }
-        panic!("Can't find target piece! Agh"); // FIXME: Improve error handling.
+    ///Returns whether a piece of this type and colour can ever, in any legal game, stand at the given coordinate.
+    fn IsPossibleSquare(pieceType : &PieceType, isRed : bool, x : usize, y : usize) -> bool {
+        // Everything below is written from Red's perspective, so flip Black's pieces over
+        let y = if isRed { y } else { BLACK_ROW - y };
+        return match pieceType {
+            PieceType::King => BoardState::IsPalace(x, y) && y <= 2,
+            PieceType::Advisor => matches!((x,y), (3,0) | (5,0) | (4,1) | (3,2) | (5,2)),
+            PieceType::Elephant => matches!((x,y), (2,0) | (6,0) | (0,2) | (4,2) | (8,2) | (2,4) | (6,4)),
+            PieceType::Pawn => y >= 3 && (y >= BLACK_RIVER || x.is_multiple_of(2)), // Can only go sideways once across the river
+            PieceType::Horse | PieceType::Cannon | PieceType::Rook => true
+        };
     }
 
     ///To be used exclusively by the FEN reader. Does checking to ensure there aren't too many of any particular piece
-    fn spawnPiece(&mut self, cara : char, coord : Coord ) {
-        let set : &mut PieceSet;
-        let piece : Piece = Piece::new(cara,PackedCoord::new_from_Coord(coord));
-        if piece.isRed {
-            set = &mut self.redPieces;
-        } else {
-            set = &mut self.blackPieces;
+    fn spawnPiece(&mut self, pieceIndex : PieceIndex, coord : Coord ) -> Result<(),FenError> {
+        let piece : Piece = Piece::new(pieceIndex.pieceType().unwrap(),pieceIndex.isRed(),PackedCoord::new_from_Coord(coord));
+        if !BoardState::IsPossibleSquare(&piece.pieceType, piece.isRed, coord.0, coord.1) {
+            return Err(FenError::ImpossibleSquare(piece.getChar(), coord));
         }
-        self.squares[coord.1][coord.0].pieceIndex = PieceIndex::new(cara);
-        let packedCoord = piece.loc;
-        match piece.pieceType {
-            PieceType::King => {
-                set.King = packedCoord;
-                self.squares[coord.1][coord.0].pieceIndex = PieceIndex::new(cara);
-            },
-            PieceType::Rook => Self::spawnSpecificPiece(&mut set.Rooks,&packedCoord),
-            PieceType::Cannon => Self::spawnSpecificPiece(&mut set.Cannons,&packedCoord),
-            PieceType::Horse => Self::spawnSpecificPiece(&mut set.Horses,&packedCoord),
-            PieceType::Elephant => Self::spawnSpecificPiece(&mut set.Elephants,&packedCoord),
-            PieceType::Advisor => Self::spawnSpecificPiece(&mut set.Advisors,&packedCoord),
-            PieceType::Pawn => Self::spawnSpecificPiece(&mut set.Pawns,&packedCoord),
-        };
+        let set = self.pieceSetMut(piece.isRed);
+        if !Self::spawnSpecificPiece(set.slotsMut(piece.pieceType), &piece.loc) {
+            return Err(FenError::TooManyPieces(piece.getChar()));
+        }
+        self.squares[coord.1][coord.0].pieceIndex = pieceIndex;
+        return Ok(());
     }
 
-    pub fn loadFEN(&mut self, fenStr : &str) {
-        let mut x : usize = 0;
-        let mut y : usize = 9;
-        // Doing all this so that we can resume iteration under the metadata for loop later
-        let mut iterator = fenStr.chars().into_iter();
-        for cara in iterator.by_ref() { // First read in the board
-            if cara == '/' {
-                y -= 1;
-                x = 0;
-                continue;
-            }
-            if cara.is_numeric() {
-                x += cara.to_digit(10).unwrap_or(1) as usize;
-                continue;
-            }
-            if cara.is_whitespace() {
-                break;
-            }
-            match cara {
-                'p' | 'P' | 'a' | 'A' | 'e' | 'E' | 'h' | 'H' | 'c' | 'C' | 'r' | 'R' | 'k' | 'K'  => {
-                    self.spawnPiece(cara,(x,y));
-                },
-                'b' => { // For some reason xiangqi.com uses this to mark Elephants sometimes.
-                    self.spawnPiece('e',(x,y));
-                },
-                'B' => {
-                    self.spawnPiece('E',(x,y));
-                },
-                'N' => {
-                    self.spawnPiece('H',(x,y));
+    ///Reads the FEN onto this board, which is expected to be empty.
+    pub fn loadFEN(&mut self, fenStr : &str) -> Result<(),FenError> {
+        let mut fields = fenStr.split_ascii_whitespace();
+        let boardField = fields.next().ok_or(FenError::Empty)?;
+        let ranks : Vec<&str> = boardField.split('/').collect();
+        if ranks.len() != 10 {
+            return Err(FenError::WrongRankCount(ranks.len()));
+        }
+        for (index, rank) in ranks.iter().enumerate() { // First read in the board
+            let y = BLACK_ROW - index;
+            let mut x : usize = 0;
+            for cara in rank.chars() {
+                if let Some(digit) = cara.to_digit(10) {
+                    x += digit as usize;
+                    continue;
                 }
-                'n' => {
-                    self.spawnPiece('h',(x,y));
+                // For some reason xiangqi.com uses B to mark Elephants sometimes, hence new_from_char taking it.
+                let pieceIndex = PieceIndex::new_from_char(cara).ok_or(FenError::UnknownPiece(cara))?;
+                if x >= 9 {
+                    return Err(FenError::WrongRankLength(y));
                 }
-                ' ' => break,
-                _ => {}
-            };
-            x+=1;
-        }
-        if y != 0 || x != 9 {
-            print!("Warning: FEN was incomplete.");
-            return;
+                self.spawnPiece(pieceIndex,(x,y))?;
+                x += 1;
+            }
+            if x != 9 {
+                return Err(FenError::WrongRankLength(y));
+            }
         }
-        
-        let whoseMove = BoardState::skipWhitespace(&mut iterator);
-        if whoseMove.is_none() {
-            println!("Invalid FEN: missing metadata for whose turn it is");
-            return;
-        } 
-        let cara = whoseMove.unwrap();
-        match cara {
-            'w' | 'W' | 'r' | 'R' => {
+
+        let whoseMove = fields.next().ok_or(FenError::MissingSideToMove)?;
+        match whoseMove {
+            "w" | "W" | "r" | "R" => {
                 self.isRedTurn = true;
             }
-            'b' | 'B' => {
+            "b" | "B" => {
                 self.isRedTurn = false;
             }
             _ => {
-                println!("Invalid FEN: move marker not recognized: {}",cara);
+                return Err(FenError::BadSideToMove(whoseMove.to_string()));
+            }
+        }
+        // Everything past here is optional, since plenty of FENs in the wild stop after whose move it is.
+        // Chess' castling and en passant fields mean nothing here, so they have to be '-' if they're there at all,
+        // and some FENs leave them out altogether and go straight to the counters.
+        let mut fields = fields.peekable();
+        if fields.peek().is_some_and(|field| field.parse::<u16>().is_err()) {
+            for _ in 0..2 {
+                match fields.next() {
+                    Some("-") | None => {}
+                    Some(field) => return Err(FenError::BadPlaceholder(field.to_string()))
+                }
             }
         }
-        BoardState::skipWhitespace(&mut iterator); // -
-        BoardState::skipWhitespace(&mut iterator); // -
-        BoardState::readNumber(&mut iterator); // 0
-        self.plyNumber = (BoardState::readNumber(&mut iterator) - 1) * 2;
+        if let Some(halfMoves) = fields.next() {
+            self.halfMoveClock = halfMoves.parse::<u16>().map_err(|_| FenError::BadMoveCounter(halfMoves.to_string()))?;
+        }
+        let fullMoves = match fields.next() {
+            Some(token) => token.parse::<i16>().ok()
+                .filter(|number| *number >= 1 && *number <= i16::MAX / 2)
+                .ok_or(FenError::BadMoveCounter(token.to_string()))?,
+            None => 1
+        };
+        if let Some(extra) = fields.next() {
+            return Err(FenError::TrailingField(extra.to_string()));
+        }
+        self.plyNumber = (fullMoves - 1) * 2;
         if !self.isRedTurn { // black's move, so we have 1 extra ply :)
             self.plyNumber += 1;
         }
         if self.redPieces.King == DEAD_PIECE_PACKEDCOORD {
-            panic!("Invalid FEN: Red King is missing");
+            return Err(FenError::MissingKing(true));
         }
         if self.blackPieces.King == DEAD_PIECE_PACKEDCOORD {
-            panic!("Invalid FEN: Black King is missing");
+            return Err(FenError::MissingKing(false));
         }
 
         debug_assert!(self.plyNumber % 2 != (self.isRedTurn as i16)); // ply is even when it's Red's turn and odd when it's Black's
-
+        self.zobristKey = self.computeZobristKey();
+        return Ok(());
     }
 
     /// Outputs a FEN which describes the board position.
@@ -236,8 +231,7 @@ impl BoardState {
         } else {
             fenString.push('b');
         }
-        fenString.push_str(" - - 0 ");
-        fenString.push_str(((self.plyNumber / 2) + 1).to_string().as_str());
+        fenString.push_str(format!(" - - {} {}", self.halfMoveClock, (self.plyNumber / 2) + 1).as_str());
 
         return fenString;
     }
@@ -257,96 +251,96 @@ impl BoardState {
     }
     /// Returns the value of the position w/o depth evaluation; the "aesthetic" value of the board.
     /// Positive value means Red is winning, negative value means Black is winning.
-    pub fn getValue(&self) -> ScoreF32 {
-        let mut sum : f32 = 0f32;
+    pub fn getValue(&self) -> ScoreI32 {
+        let mut sum : i32 = 0; // In centipawns
         let mut foundRed : bool = false;
         let mut foundBlack : bool = false;
 
-        if self.isRedTurn { sum += 0.125f32;}
+        if self.isRedTurn { sum += 13;}
 
         for piece in self.IteratePieces(true) {
             sum += match piece.pieceType {
                 PieceType::Pawn => {
                     if piece.loc.y() >= BLACK_RIVER {
-                        2f32
+                        200
                     }
                     else {
-                        1f32
+                        100
                     }
                 },
                 PieceType::Advisor => match piece.loc.y() {
-                    0 => 1.75f32,
-                    1..=2 => 2f32,
+                    0 => 175,
+                    1..=2 => 200,
                     _ => unreachable!()
                 },
-                PieceType::Elephant => 2.0f32,
+                PieceType::Elephant => 200,
                 PieceType::Horse => {
                     match piece.loc.x() {
-                        0 => 3.5f32,
-                        1..=7 => 4f32,
-                        8 => 3.5f32,
+                        0 => 350,
+                        1..=7 => 400,
+                        8 => 350,
                         _ => unsafe {unreachable_unchecked()}
                     }
                 },
-                PieceType::Cannon => 4.5f32,
+                PieceType::Cannon => 450,
                 PieceType::Rook => {
                     match piece.loc.y() {
-                        9 => 9f32,
-                        0..=8 => 8.5f32,
+                        9 => 900,
+                        0..=8 => 850,
                         _ => unreachable!()
                     }
                 },
-                PieceType::King => {foundRed = true; 0f32} // we handle this differently
+                PieceType::King => {foundRed = true; 0} // we handle this differently
             }
         }
         for piece in self.IteratePieces(false) {
             sum -= match piece.pieceType {
                 PieceType::Pawn => {
                     if piece.loc.y() <= RED_RIVER {
-                        2f32
+                        200
                     }
                     else {
-                        1f32
+                        100
                     }
                 },
                 PieceType::Advisor => match piece.loc.y() {
-                    9 => 1.75f32,
-                    7..=8 => 2f32,
+                    9 => 175,
+                    7..=8 => 200,
                     _ => unreachable!()
                 },
-                PieceType::Elephant => 2.0f32,
+                PieceType::Elephant => 200,
                 PieceType::Horse => {
                     match piece.loc.x() {
-                        0 => 3.5f32,
-                        1..=7 => 4f32,
-                        8 => 3.5f32,
+                        0 => 350,
+                        1..=7 => 400,
+                        8 => 350,
                         _ => unreachable!()
                     }
                 },
-                PieceType::Cannon => 4.5f32,
+                PieceType::Cannon => 450,
                 PieceType::Rook => {
                     match piece.loc.y() {
-                        0 => 9f32,
-                        1..=9 => 8.5f32,
+                        0 => 900,
+                        1..=9 => 850,
                         _ => unreachable!()
                     }
                 },
-                PieceType::King => {foundBlack = true; 0f32} // we handle this differently
+                PieceType::King => {foundBlack = true; 0} // we handle this differently
             }
         }
         if !foundBlack {
-            return score::RED_WON;
+            return ScoreI32::RED_WON;
         }
         if !foundRed {
-            return score::BLACK_WON;
+            return ScoreI32::BLACK_WON;
         }
-        return ScoreF32::new(sum);
+        return ScoreI32::new_from_eval(sum);
     }
 
     fn IsSameColour(&self, x: usize, y : usize, isRed : bool) -> bool {
         debug_assert!(x < 9 && y < 10, "wtf");
         let tile : &Tile = &self.squares[y][x];
-        return tile.hasPiece() && tile.pieceIndex.asChar().is_ascii_uppercase() == isRed;
+        return tile.hasPiece() && tile.pieceIndex.isRed() == isRed;
     }
 
     fn TryMove(&self, x: usize, y: usize, isRed : bool, moveArr : &mut Vec<PackedCoord> ) {
@@ -354,6 +348,14 @@ impl BoardState {
             moveArr.push(PackedCoord::new_from_usize(x,y));
         }
     }
+
+    ///Like TryMove, but refuses if something is sitting on the elephant's eye (the point midway between start & end).
+    fn TryElephantMove(&self, from_x : usize, from_y : usize, x : usize, y : usize, isRed : bool, moveArr : &mut Vec<PackedCoord>) {
+        if self.squares[(from_y + y) / 2][(from_x + x) / 2].hasPiece() {
+            return;
+        }
+        self.TryMove(x, y, isRed, moveArr);
+    }
     /// Returns whether the given coordinate is within a palace.
     fn IsPalace(x : usize, y : usize) -> bool {
         return match x {
@@ -404,16 +406,122 @@ impl BoardState {
         return ret;
     }
 
-    //FIXME: This is too expensive to use in the move sorting algorithm :(
+    ///Returns only the moves which do not leave the moving side's king attacked (flying general included). <br/>
+    ///Coordinates returned are in (x,y) order.
+    pub fn getLegalMoves(&self) -> Vec<PackedMove> {
+        let mut ret = self.getAllMoves();
+        let mut scratch = self.clone(); // One copy up front rather than one per move
+        ret.retain(|packedMove| scratch.isLegalMove(*packedMove));
+        return ret;
+    }
+
+    ///Returns whether this pseudo-legal move (as in, one that getAllMoves would give) keeps the mover's king safe.
+    ///Plays the move to find out, but leaves the board exactly as it was found.
+    pub fn isLegalMove(&mut self, packedMove : PackedMove) -> bool {
+        let isRed = self.isRedTurn;
+        let undo = self.make_move(packedMove);
+        let ret = !self.isKingAttacked(isRed);
+        self.unmake_move(undo);
+        return ret;
+    }
+
+    #[allow(dead_code)] // Needed for tests
+    pub fn countLegalMoves(&self) -> i32 {
+        return self.getLegalMoves().len() as i32;
+    }
+
+    ///Counts the number of legal move sequences of the given length from this position.
+    ///Only really useful for checking the move generator against known-good numbers.
+    pub fn perft(&self, depth : u32) -> u64 {
+        return self.clone().perftInPlace(depth);
+    }
+
+    fn perftInPlace(&mut self, depth : u32) -> u64 {
+        if depth == 0 {
+            return 1;
+        }
+        let moves = self.getLegalMoves();
+        if depth == 1 { // No need to actually play out the last ply
+            return moves.len() as u64;
+        }
+        let mut sum : u64 = 0;
+        for packedMove in moves {
+            let undo = self.make_move(packedMove);
+            sum += self.perftInPlace(depth - 1);
+            self.unmake_move(undo);
+        }
+        return sum;
+    }
+
+    ///Same as perft, but broken down by which move was played first.
+    pub fn divide(&self, depth : u32) -> Vec<(PackedMove,u64)> {
+        if depth == 0 {
+            return Vec::new();
+        }
+        return self.getLegalMoves().into_iter()
+            .map(|packedMove| (packedMove, self.branch(packedMove).perft(depth - 1)))
+            .collect();
+    }
+
     pub fn isInCheck(&self) -> bool {
-        let kingPosition = if self.isRedTurn { self.redPieces.King} else {self.blackPieces.King};
-        for piece in self.IteratePieces(!self.isRedTurn) {
-            let mut coords : Vec<PackedCoord> = Vec::with_capacity(16);
-            coords = self.getPieceMoves(&piece, coords);
-            for coord in coords.as_slice().iter() {
-                if coord == &kingPosition {
-                    return true;
+        return self.isKingAttacked(self.isRedTurn);
+    }
+
+    ///Returns whether the king of the given colour could be captured by the other side, were it their move.
+    ///Looks outward from the king rather than generating every enemy move, so it's cheap enough to run per-move.
+    pub fn isKingAttacked(&self, isRed : bool) -> bool {
+        let kingPosition = if isRed { self.redPieces.King } else { self.blackPieces.King };
+        if kingPosition == DEAD_PIECE_PACKEDCOORD { // Can't attack what isn't there
+            return false;
+        }
+        let x = kingPosition.x() as i32;
+        let y = kingPosition.y() as i32;
+        let enemy = |pieceType : PieceType| -> PieceIndex {
+            return PieceIndex::new(pieceType, !isRed);
+        };
+        let pieceAt = |x : i32, y : i32| -> PieceIndex {
+            if !(0..9).contains(&x) || !(0..10).contains(&y) {
+                return Tile::new().pieceIndex;
+            }
+            return self.squares[y as usize][x as usize].pieceIndex;
+        };
+
+        //Rooks, cannons and the flying general, which all work along ranks & files
+        for (dx,dy) in [(0,1),(0,-1),(1,0),(-1,0)] {
+            let mut march_x = x + dx;
+            let mut march_y = y + dy;
+            let mut foundHoppable = false;
+            while (0..9).contains(&march_x) && (0..10).contains(&march_y) {
+                let pieceIndex = pieceAt(march_x, march_y);
+                if !pieceIndex.isEmpty() {
+                    if foundHoppable {
+                        if pieceIndex == enemy(PieceType::Cannon) {
+                            return true;
+                        }
+                        break;
+                    }
+                    if pieceIndex == enemy(PieceType::Rook) || (dx == 0 && pieceIndex == enemy(PieceType::King)) {
+                        return true;
+                    }
+                    foundHoppable = true;
                 }
+                march_x += dx;
+                march_y += dy;
+            }
+        }
+
+        //Horses; the leg that would block the horse is always diagonally adjacent to the king
+        for (dx,dy) in [(1,2),(-1,2),(1,-2),(-1,-2),(2,1),(-2,1),(2,-1),(-2,-1)] {
+            if pieceAt(x + dx, y + dy) == enemy(PieceType::Horse) && pieceAt(x + dx.signum(), y + dy.signum()).isEmpty() {
+                return true;
+            }
+        }
+
+        //Pawns, which attack forward (towards the enemy's back rank) and sideways
+        let pawnForward = if isRed { 1 } else { -1 };
+        for (dx,dy) in [(0,pawnForward),(1,0),(-1,0)] {
+            if pieceAt(x + dx, y + dy) == enemy(PieceType::Pawn) {
+                return true;
             }
         }
         return false;
@@ -423,9 +531,7 @@ impl BoardState {
     ///Coordinates in (x,y), "from->to" order.
     pub fn branch(&self, newMove : PackedMove) -> Self {
         let mut ret : Self = self.clone();
-        ret.updatePieceLoc(newMove);
-        ret.isRedTurn = !ret.isRedTurn;
-        ret.plyNumber += 1;
+        ret.make_move(newMove);
         return ret;
     }
 
@@ -436,42 +542,81 @@ impl BoardState {
         return self.blackPieces.King != DEAD_PIECE_PACKEDCOORD;
     }
 
-    fn updatePieceLoc(&mut self, newMove : PackedMove) { // FIXME: Needs to be made faster.
-        //Update the tile
-        let caraOfUpdatedPiece : char = PackedMove::indexStart(&self.squares, &newMove).pieceIndex.asChar();
-        if !newMove.killsPiece() { // If we're not moving this piece to heck
-            if PackedMove::indexEnd(&self.squares, &newMove).hasPiece() { // if a piece is already there
-                self.isRedTurn = !self.isRedTurn; // FIXME: wtf
-                let mut deathMove = newMove.clone(); // Ah, right, the death move
-                deathMove.data = (deathMove.data << 8) | 0b1111_1111; // the deathmove to kill this piece
-                self.updatePieceLoc(deathMove); // the deathmove chosen specifically to kill this piece
-                self.isRedTurn = !self.isRedTurn; // the move of death.
-            }
-            let oldTile : &mut Tile = PackedMove::indexStartMut(&mut self.squares, &newMove); // pick up the piece
-            PackedMove::indexEndMut(&mut self.squares, &newMove).pieceIndex = oldTile.take(); // place it down
-            //debug_assert!(self.squares[newMove.1.1][newMove.1.0].hasPiece());
-        }
-        //Update the PieceSet location
-        let set : &mut PieceSet;
-        if self.isRedTurn {
-            set = &mut self.redPieces;
-        } else {
-            set = &mut self.blackPieces;
-        };
+    ///Plays the given move onto this board, returning what's needed to take it back with unmake_move.
+    ///Does no legality checking whatsoever.
+    pub fn make_move(&mut self, newMove : PackedMove) -> UndoRecord {
         let moveStart = newMove.start();
         let moveEnd = newMove.end();
-        match caraOfUpdatedPiece {
-            'k'|'K' => {
-                set.King = moveEnd;
-            },
-            'r'|'R' => Self::setSpecificPiece(&mut set.Rooks,&moveEnd,&moveStart),
-            'c'|'C' => Self::setSpecificPiece(&mut set.Cannons,&moveEnd,&moveStart),
-            'h'|'H' => Self::setSpecificPiece(&mut set.Horses,&moveEnd,&moveStart),
-            'e'|'E' => Self::setSpecificPiece(&mut set.Elephants,&moveEnd,&moveStart),
-            'a'|'A' => Self::setSpecificPiece(&mut set.Advisors,&moveEnd,&moveStart),
-            'p'|'P' => Self::setSpecificPiece(&mut set.Pawns,&moveEnd,&moveStart),
-            _ => unreachable!("'{}' is an impossible piece character",caraOfUpdatedPiece)
+        let movingPiece = PackedMove::indexStartMut(&mut self.squares, &newMove).take(); // pick up the piece
+        let capturedPiece = PackedMove::indexEnd(&self.squares, &newMove).pieceIndex;
+        let mut undo = UndoRecord {
+            playedMove : newMove,
+            captured : capturedPiece,
+            capturedSlot : 0,
+            plyNumber : self.plyNumber,
+            halfMoveClock : self.halfMoveClock,
+            zobristKey : self.zobristKey
         };
+        if let Some(capturedType) = capturedPiece.pieceType() { // if a piece is already there, off to heck with it
+            undo.capturedSlot = self.pieceSetMut(capturedPiece.isRed()).movePiece(capturedType, moveEnd, DEAD_PIECE_PACKEDCOORD) as u8;
+            self.zobristKey ^= zobrist::pieceKey(capturedPiece, moveEnd);
+        }
+        self.zobristKey ^= zobrist::pieceKey(movingPiece, moveStart) ^ zobrist::pieceKey(movingPiece, moveEnd) ^ zobrist::BLACK_TO_MOVE_KEY;
+        PackedMove::indexEndMut(&mut self.squares, &newMove).pieceIndex = movingPiece; // place it down
+        let movingType = movingPiece.pieceType().unwrap_or_else(|| unreachable!("Tried to move a piece from the empty tile at {:?}",moveStart.makeCoord()));
+        self.pieceSetMut(movingPiece.isRed()).movePiece(movingType, moveStart, moveEnd);
+
+        self.isRedTurn = !self.isRedTurn;
+        self.plyNumber += 1;
+        self.halfMoveClock = if capturedPiece.isEmpty() { self.halfMoveClock.saturating_add(1) } else { 0 };
+        return undo;
+    }
+
+    ///Takes back the move described by this UndoRecord. Only valid if it was the last move made on this board.
+    pub fn unmake_move(&mut self, undo : UndoRecord) {
+        let moveStart = undo.playedMove.start();
+        let moveEnd = undo.playedMove.end();
+        self.isRedTurn = !self.isRedTurn;
+        self.plyNumber = undo.plyNumber;
+        self.halfMoveClock = undo.halfMoveClock;
+        self.zobristKey = undo.zobristKey;
+
+        let movingPiece = PackedMove::indexEndMut(&mut self.squares, &undo.playedMove).take();
+        let movingType = movingPiece.pieceType().unwrap_or_else(|| unreachable!("Tried to unmake a move onto the empty tile at {:?}",moveEnd.makeCoord()));
+        self.pieceSetMut(movingPiece.isRed()).movePiece(movingType, moveEnd, moveStart);
+        PackedMove::indexStartMut(&mut self.squares, &undo.playedMove).pieceIndex = movingPiece;
+
+        if let Some(capturedType) = undo.captured.pieceType() { // bring 'em back
+            self.pieceSetMut(undo.captured.isRed()).slotsMut(capturedType)[undo.capturedSlot as usize] = moveEnd;
+            PackedMove::indexEndMut(&mut self.squares, &undo.playedMove).pieceIndex = undo.captured;
+        }
+    }
+
+    ///The Zobrist key of this position; equal positions (same pieces, same side to move) always have equal keys,
+    ///no matter how they were reached or what move number it is.
+    pub fn getZobristKey(&self) -> u64 {
+        return self.zobristKey;
+    }
+
+    ///Works out the Zobrist key from scratch. Slow; the board keeps its own key up to date as moves are made.
+    pub fn computeZobristKey(&self) -> u64 {
+        let mut key : u64 = 0;
+        for isRed in [true, false] {
+            for piece in self.IteratePieces(isRed) {
+                key ^= zobrist::pieceKey(PieceIndex::new(piece.pieceType, isRed), piece.loc);
+            }
+        }
+        if !self.isRedTurn {
+            key ^= zobrist::BLACK_TO_MOVE_KEY;
+        }
+        return key;
+    }
+
+    fn pieceSetMut(&mut self, isRed : bool) -> &mut PieceSet {
+        if isRed {
+            return &mut self.redPieces;
+        }
+        return &mut self.blackPieces;
     }
 
     ///Gets the moves for the given piece. <br/>
@@ -545,65 +690,65 @@ impl BoardState {
                 match (x,y) {
                     //RED ELEPHANTS
                     (2,0) => {
-                        self.TryMove(0, 2, piece.isRed, &mut moveArr);
-                        self.TryMove(4, 2, piece.isRed, &mut moveArr);
+                        self.TryElephantMove(x, y, 0, 2, piece.isRed, &mut moveArr);
+                        self.TryElephantMove(x, y, 4, 2, piece.isRed, &mut moveArr);
                     }
                     (6,0) => {
-                        self.TryMove(8, 2, piece.isRed, &mut moveArr);
-                        self.TryMove(4, 2, piece.isRed, &mut moveArr);
+                        self.TryElephantMove(x, y, 8, 2, piece.isRed, &mut moveArr);
+                        self.TryElephantMove(x, y, 4, 2, piece.isRed, &mut moveArr);
                     }
                     (0,2) => {
-                        self.TryMove(2, 4, piece.isRed, &mut moveArr);
-                        self.TryMove(2, 0, piece.isRed, &mut moveArr);
+                        self.TryElephantMove(x, y, 2, 4, piece.isRed, &mut moveArr);
+                        self.TryElephantMove(x, y, 2, 0, piece.isRed, &mut moveArr);
                     },
                     (4,2) => {
-                        self.TryMove(2, 4, piece.isRed, &mut moveArr); //up and left
-                        self.TryMove(6, 4, piece.isRed, &mut moveArr); //up and right
-                        self.TryMove(2, 0, piece.isRed, &mut moveArr); //down and left
-                        self.TryMove(6, 0, piece.isRed, &mut moveArr); //down and right
+                        self.TryElephantMove(x, y, 2, 4, piece.isRed, &mut moveArr); //up and left
+                        self.TryElephantMove(x, y, 6, 4, piece.isRed, &mut moveArr); //up and right
+                        self.TryElephantMove(x, y, 2, 0, piece.isRed, &mut moveArr); //down and left
+                        self.TryElephantMove(x, y, 6, 0, piece.isRed, &mut moveArr); //down and right
                     },
                     (8,2) => {
-                        self.TryMove(6, 4, piece.isRed, &mut moveArr);
-                        self.TryMove(6, 0, piece.isRed, &mut moveArr);
+                        self.TryElephantMove(x, y, 6, 4, piece.isRed, &mut moveArr);
+                        self.TryElephantMove(x, y, 6, 0, piece.isRed, &mut moveArr);
                     },
                     (2,4) => {
-                        self.TryMove(0, 2, piece.isRed, &mut moveArr);
-                        self.TryMove(4, 2, piece.isRed, &mut moveArr);
+                        self.TryElephantMove(x, y, 0, 2, piece.isRed, &mut moveArr);
+                        self.TryElephantMove(x, y, 4, 2, piece.isRed, &mut moveArr);
                     },
                     (6,4) => {
-                        self.TryMove(4, 2, piece.isRed, &mut moveArr);
-                        self.TryMove(8, 2, piece.isRed, &mut moveArr);
+                        self.TryElephantMove(x, y, 4, 2, piece.isRed, &mut moveArr);
+                        self.TryElephantMove(x, y, 8, 2, piece.isRed, &mut moveArr);
                     },
                     //BLACK ELEPHANTS
                     (2,9) => {
-                        self.TryMove(0, 7, piece.isRed, &mut moveArr);
-                        self.TryMove(4, 7, piece.isRed, &mut moveArr);
+                        self.TryElephantMove(x, y, 0, 7, piece.isRed, &mut moveArr);
+                        self.TryElephantMove(x, y, 4, 7, piece.isRed, &mut moveArr);
                     }
                     (6,9) => {
-                        self.TryMove(8, 7, piece.isRed, &mut moveArr);
-                        self.TryMove(4, 7, piece.isRed, &mut moveArr);
+                        self.TryElephantMove(x, y, 8, 7, piece.isRed, &mut moveArr);
+                        self.TryElephantMove(x, y, 4, 7, piece.isRed, &mut moveArr);
                     }
                     (0,7) => {
-                        self.TryMove(2, 5, piece.isRed, &mut moveArr);
-                        self.TryMove(2, 9, piece.isRed, &mut moveArr);
+                        self.TryElephantMove(x, y, 2, 5, piece.isRed, &mut moveArr);
+                        self.TryElephantMove(x, y, 2, 9, piece.isRed, &mut moveArr);
                     },
                     (4,7) => {
-                        self.TryMove(2, 5, piece.isRed, &mut moveArr); //up and left
-                        self.TryMove(6, 5, piece.isRed, &mut moveArr); //up and right
-                        self.TryMove(2, 9, piece.isRed, &mut moveArr); //down and left
-                        self.TryMove(6, 9, piece.isRed, &mut moveArr); //down and right
+                        self.TryElephantMove(x, y, 2, 5, piece.isRed, &mut moveArr); //up and left
+                        self.TryElephantMove(x, y, 6, 5, piece.isRed, &mut moveArr); //up and right
+                        self.TryElephantMove(x, y, 2, 9, piece.isRed, &mut moveArr); //down and left
+                        self.TryElephantMove(x, y, 6, 9, piece.isRed, &mut moveArr); //down and right
                     },
                     (8,7) => {
-                        self.TryMove(6, 5, piece.isRed, &mut moveArr);
-                        self.TryMove(6, 9, piece.isRed, &mut moveArr);
+                        self.TryElephantMove(x, y, 6, 5, piece.isRed, &mut moveArr);
+                        self.TryElephantMove(x, y, 6, 9, piece.isRed, &mut moveArr);
                     },
                     (2,5) => {
-                        self.TryMove(0, 7, piece.isRed, &mut moveArr);
-                        self.TryMove(4, 7, piece.isRed, &mut moveArr);
+                        self.TryElephantMove(x, y, 0, 7, piece.isRed, &mut moveArr);
+                        self.TryElephantMove(x, y, 4, 7, piece.isRed, &mut moveArr);
                     },
                     (6,5) => {
-                        self.TryMove(4, 7, piece.isRed, &mut moveArr);
-                        self.TryMove(8, 7, piece.isRed, &mut moveArr);
+                        self.TryElephantMove(x, y, 4, 7, piece.isRed, &mut moveArr);
+                        self.TryElephantMove(x, y, 8, 7, piece.isRed, &mut moveArr);
                     },
                     _ => {
                         unreachable!("Invalid position for elephant!");
@@ -741,9 +886,7 @@ impl BoardState {
 
 impl std::hash::Hash for BoardState {
     fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
-        self.squares.hash(state);
-        self.isRedTurn.hash(state);
-        self.plyNumber.hash(state);
-        //Doesn't need to use the PieceSets since they're actually redundant information :)
+        //The Zobrist key already covers the squares & whose turn it is, and the PieceSets are redundant information :)
+        self.zobristKey.hash(state);
     }
 }
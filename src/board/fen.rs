@@ -0,0 +1,54 @@
+use std::fmt::{Display, Formatter};
+
+use super::Coord;
+
+/// Why BoardState::try_from_fen turned a FEN down.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum FenError {
+    /// The FEN was empty, or only whitespace.
+    Empty,
+    /// The board didn't have exactly 10 ranks in it.
+    WrongRankCount(usize),
+    /// A rank (given by its Y index) didn't describe exactly 9 files.
+    WrongRankLength(usize),
+    /// A character in the board section that isn't a piece, digit or slash.
+    UnknownPiece(char),
+    /// Either side had more of a piece than the game allows (1 king, 5 pawns, 2 of everything else).
+    TooManyPieces(char),
+    /// The piece could never legally stand on this square, like an advisor outside its palace.
+    ImpossibleSquare(char, Coord),
+    /// The side given by the bool (true for Red) has no king.
+    MissingKing(bool),
+    /// There was no side-to-move field after the board.
+    MissingSideToMove,
+    /// The side-to-move field was something other than w, r or b.
+    BadSideToMove(String),
+    /// The half-move or full-move counter wasn't a (reasonable) number.
+    BadMoveCounter(String),
+    /// Something other than '-' where chess keeps castling rights and the en passant square.
+    BadPlaceholder(String),
+    /// More fields after the full-move counter.
+    TrailingField(String),
+}
+
+impl Display for FenError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        return match self {
+            FenError::Empty => write!(f, "Invalid FEN: string is empty"),
+            FenError::WrongRankCount(count) => write!(f, "Invalid FEN: expected 10 ranks, found {}", count),
+            FenError::WrongRankLength(y) => write!(f, "Invalid FEN: rank {} does not have 9 files", y),
+            FenError::UnknownPiece(cara) => write!(f, "Invalid FEN: unknown piece '{}'", cara),
+            FenError::TooManyPieces(cara) => write!(f, "Invalid FEN: too many pieces of type '{}'", cara),
+            FenError::ImpossibleSquare(cara, coord) => write!(f, "Invalid FEN: '{}' can never be at ({},{})", cara, coord.0, coord.1),
+            FenError::MissingKing(true) => write!(f, "Invalid FEN: Red King is missing"),
+            FenError::MissingKing(false) => write!(f, "Invalid FEN: Black King is missing"),
+            FenError::MissingSideToMove => write!(f, "Invalid FEN: missing metadata for whose turn it is"),
+            FenError::BadSideToMove(token) => write!(f, "Invalid FEN: move marker not recognized: {}", token),
+            FenError::BadMoveCounter(token) => write!(f, "Invalid FEN: move counter not recognized: {}", token),
+            FenError::BadPlaceholder(token) => write!(f, "Invalid FEN: expected '-' but found {}", token),
+            FenError::TrailingField(token) => write!(f, "Invalid FEN: unexpected field after the move counters: {}", token),
+        };
+    }
+}
+
+impl std::error::Error for FenError {}
@@ -34,11 +34,6 @@ impl PackedMove {
             data : ((start.data as u16) << 8u16) | ((end.data as u16))
         };
     }
-    fn getLetter(x_val : u16) -> char {
-        const LETTERS : &'static [u8] = "abcdefghij".as_bytes();
-        return LETTERS[x_val as usize] as char;
-    }
-
     ///Indexes for the starting coordinate.
     pub fn indexStart<'a>(arr : &'a TileGrid, ind : &PackedMove) -> &'a Tile {
         return &arr
@@ -72,19 +67,59 @@ impl PackedMove {
         return PackedCoord { data: (self.data & 0b1111_1111) as u8 };
     }
 
-    pub const fn killsPiece(&self) -> bool { // True if the piece moves to an encoded DEAD_PIECE_COORD
-        return (self.data & 0b0000_0000_1111_1111u16) == 0b1111_1111u16;
+    ///Reads a move written in coordinates, same as parse() but without caring why it didn't work.
+    pub fn new_from_coordString(moveStr : &str) -> Option<Self> {
+        return moveStr.parse().ok();
     }
 
-    fn formatThis(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    ///The move written in coordinates, like "h2e2". This is what Display gives too.
+    pub fn coordString(&self) -> String {
         if self.data == 0b1111_1111_1111_1111 {
-            return write!(f,"????");
+            return String::from("????");
         }
-        let x_start = (self.data & 0b1111_0000_0000_0000) >> 12u16;
-        let y_start = (self.data & 0b0000_1111_0000_0000) >> 8u16;
-        let x_end = (self.data & 0b0000_0000_1111_0000) >> 4u16;
-        let y_end = self.data & 0b0000_0000_0000_1111;
-        write!(f, "{}{}{}{}", Self::getLetter(y_start),x_start+1,Self::getLetter(y_end),x_end+1)
+        return format!("{}{}", self.start().coordString(), self.end().coordString());
+    }
+
+    fn formatThis(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        return write!(f, "{}", self.coordString());
+    }
+}
+
+/// Why a string isn't a coordinate move like h2e2.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum MoveParseError {
+    /// It wasn't 4 characters long, or 5 with a dash in the middle.
+    WrongLength(String),
+    /// One of the squares wasn't on the board, like "j3" or "a10".
+    BadSquare(String),
+}
+
+impl std::fmt::Display for MoveParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        return match self {
+            MoveParseError::WrongLength(moveStr) => write!(f, "Invalid move: '{}' isn't two squares like h2e2", moveStr),
+            MoveParseError::BadSquare(square) => write!(f, "Invalid move: '{}' isn't a square on the board", square),
+        };
+    }
+}
+
+impl std::error::Error for MoveParseError {}
+
+/// Moves are written the way UCCI (and ICCS) write them, like "h2e2" or "h2-e2": files a-i from Red's left, ranks 0-9 from Red's side.
+/// Doesn't care about case. Doesn't check that the move is legal either, only that both squares are on the board.
+impl std::str::FromStr for PackedMove {
+    type Err = MoveParseError;
+
+    fn from_str(moveStr : &str) -> Result<Self, Self::Err> {
+        let bytes = moveStr.as_bytes();
+        let endIndex = match bytes.len() {
+            4 => 2,
+            5 if bytes[2] == b'-' => 3,
+            _ => return Err(MoveParseError::WrongLength(moveStr.to_string()))
+        };
+        let square = |index : usize| PackedCoord::new_from_coordBytes(bytes[index], bytes[index + 1])
+            .ok_or_else(|| MoveParseError::BadSquare(String::from_utf8_lossy(&bytes[index..index + 2]).into_owned()));
+        return Ok(Self::new_from_packed(square(0)?, square(endIndex)?));
     }
 }
 
@@ -132,6 +167,20 @@ impl PackedCoord {
         );
     }
 
+    ///Reads a square like "h2" (as two bytes), or gives None if it's off the board.
+    pub fn new_from_coordBytes(file : u8, rank : u8) -> Option<Self> {
+        let file = file.to_ascii_lowercase();
+        if !(b'a'..=b'i').contains(&file) || !rank.is_ascii_digit() {
+            return None;
+        }
+        return Some(Self::new_from_usize((file - b'a') as usize, (rank - b'0') as usize));
+    }
+
+    ///The square written like "h2".
+    pub fn coordString(&self) -> String {
+        return format!("{}{}", (b'a' + self.x() as u8) as char, self.y());
+    }
+
     pub fn x(&self) -> usize {
         return (self.data >> 4u8) as usize;
     }
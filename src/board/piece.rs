@@ -1,76 +1,50 @@
 use super::packedmove::{PackedCoord,DEAD_PIECE_PACKEDCOORD};
 
-#[derive(Clone, PartialEq, Eq, Hash)]
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+#[repr(u8)]
+/// Discriminants start at 1 so that a PieceIndex of 0 can mean "no piece".
 pub enum PieceType {
-    Pawn,
-    Advisor,
-    Elephant,
-    Horse,
-    Cannon,
-    Rook,
-    King
+    Pawn = 1,
+    Advisor = 2,
+    Elephant = 3,
+    Horse = 4,
+    Cannon = 5,
+    Rook = 6,
+    King = 7
 }
 
-#[derive(Clone, PartialEq, Eq, Hash)]
-pub struct Piece
-{
-    pub pieceType : PieceType,
-    pub isRed : bool,
-    pub loc : PackedCoord
-}
+impl PieceType {
+    ///Inverse of `as u8`. Returns None for anything that isn't a valid discriminant (including 0).
+    pub const fn new_from_code(code : u8) -> Option<Self> {
+        return match code {
+            1 => Some(PieceType::Pawn),
+            2 => Some(PieceType::Advisor),
+            3 => Some(PieceType::Elephant),
+            4 => Some(PieceType::Horse),
+            5 => Some(PieceType::Cannon),
+            6 => Some(PieceType::Rook),
+            7 => Some(PieceType::King),
+            _ => None
+        };
+    }
 
-impl Piece {
-    pub fn new(cara : char, newLocation : PackedCoord) -> Self {
-        match cara {
-            'p' => {
-                return Piece {pieceType : PieceType::Pawn, isRed : false, loc : newLocation};
-            },
-            'P' => {
-                return Piece {pieceType : PieceType::Pawn, isRed : true, loc : newLocation};
-            },
-            'a' => {
-                return Piece {pieceType : PieceType::Advisor, isRed : false, loc : newLocation};
-            },
-            'A' => {
-                return Piece {pieceType : PieceType::Advisor, isRed : true, loc : newLocation};
-            },
-            'e' => {
-                return Piece {pieceType : PieceType::Elephant, isRed : false, loc : newLocation};
-            },
-            'E' => {
-                return Piece {pieceType : PieceType::Elephant, isRed : true, loc : newLocation};
-            },
-            'h' | 'n' => {
-                return Piece {pieceType : PieceType::Horse, isRed : false, loc : newLocation};
-            },
-            'H' | 'N' => {
-                return Piece {pieceType : PieceType::Horse, isRed : true, loc : newLocation};
-            },
-            'c' => {
-                return Piece {pieceType : PieceType::Cannon, isRed : false, loc : newLocation};
-            },
-            'C' => {
-                return Piece {pieceType : PieceType::Cannon, isRed : true, loc : newLocation};
-            },
-            'r' => {
-                return Piece {pieceType : PieceType::Rook, isRed : false, loc : newLocation};
-            },
-            'R' => {
-                return Piece {pieceType : PieceType::Rook, isRed : true, loc : newLocation};
-            },
-            'k' => {
-                return Piece {pieceType : PieceType::King, isRed : false, loc : newLocation};
-            },
-            'K' => {
-                return Piece {pieceType : PieceType::King, isRed : true, loc : newLocation};
-            },
-            _ => {
-                panic!("Attempted to create invalid piece '{cara}'");
-            }
-        }
+    ///Reads a FEN character, returning the type and whether it's Red's. Accepts the N & B aliases for horses & elephants.
+    pub const fn new_from_char(cara : char) -> Option<(Self,bool)> {
+        let pieceType = match cara.to_ascii_lowercase() {
+            'p' => PieceType::Pawn,
+            'a' => PieceType::Advisor,
+            'e' | 'b' => PieceType::Elephant,
+            'h' | 'n' => PieceType::Horse,
+            'c' => PieceType::Cannon,
+            'r' => PieceType::Rook,
+            'k' => PieceType::King,
+            _ => return None
+        };
+        return Some((pieceType, cara.is_ascii_uppercase())); // red is uppercase, I've decided (goes with how chess FEN works)
     }
-    pub fn getChar(&self) -> char {
-        let mut character = match self.pieceType {
+
+    pub const fn asChar(&self, isRed : bool) -> char {
+        let character = match self {
             PieceType::Pawn => 'p',
             PieceType::Advisor => 'a',
             PieceType::Elephant => 'e',
@@ -79,13 +53,30 @@ impl Piece {
             PieceType::Rook => 'r',
             PieceType::King => 'k'
         };
-        if self.isRed { // red is uppercase, I've decided (goes with how chess FEN works)
-            character = character.to_ascii_uppercase();
+        if isRed {
+            return character.to_ascii_uppercase();
         }
         return character;
     }
 }
 
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct Piece
+{
+    pub pieceType : PieceType,
+    pub isRed : bool,
+    pub loc : PackedCoord
+}
+
+impl Piece {
+    pub const fn new(pieceType : PieceType, isRed : bool, newLocation : PackedCoord) -> Self {
+        return Piece {pieceType, isRed, loc : newLocation};
+    }
+    pub fn getChar(&self) -> char {
+        return self.pieceType.asChar(self.isRed);
+    }
+}
+
 impl std::fmt::Display for Piece {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{}", self.getChar())
@@ -120,6 +111,33 @@ impl Default for PieceSet {
     }
 }
 
+impl PieceSet {
+    ///The coordinate slots used for this type of piece.
+    pub fn slotsMut(&mut self, pieceType : PieceType) -> &mut [PackedCoord] {
+        return match pieceType {
+            PieceType::King => std::slice::from_mut(&mut self.King),
+            PieceType::Rook => &mut self.Rooks,
+            PieceType::Cannon => &mut self.Cannons,
+            PieceType::Horse => &mut self.Horses,
+            PieceType::Elephant => &mut self.Elephants,
+            PieceType::Advisor => &mut self.Advisors,
+            PieceType::Pawn => &mut self.Pawns,
+        };
+    }
+
+    ///Finds the piece of this type at oldCoord, moves it to newCoord, and returns which slot it was in.
+    ///Moving to DEAD_PIECE_PACKEDCOORD kills the piece; moving from it revives one.
+    pub fn movePiece(&mut self, pieceType : PieceType, oldCoord : PackedCoord, newCoord : PackedCoord) -> usize {
+        for (i, coord) in self.slotsMut(pieceType).iter_mut().enumerate() {
+            if *coord == oldCoord {
+                *coord = newCoord;
+                return i;
+            }
+        }
+        panic!("Can't find target piece! Agh");
+    }
+}
+
 pub(crate) struct PieceSetIterator<'a> {
     index : usize,
     isRed : bool,
@@ -140,26 +158,25 @@ impl<'a> PieceSetIterator<'a> {
 impl<'a> Iterator for PieceSetIterator<'a> {
     type Item = Piece;
     fn next(&mut self) -> Option<Self::Item> {
-         let mut ret: Piece = match self.index {
-            0 => Piece::new('r',self.setRef.Rooks[0]),
-            1 => Piece::new('r',self.setRef.Rooks[1]),
-            2 => Piece::new('c',self.setRef.Cannons[0]),
-            3 => Piece::new('c',self.setRef.Cannons[1]),
-            4 => Piece::new('h',self.setRef.Horses[0]),
-            5 => Piece::new('h',self.setRef.Horses[1]),
-            6 => Piece::new('e',self.setRef.Elephants[0]),
-            7 => Piece::new('e',self.setRef.Elephants[1]),
-            8..=12 => Piece::new('p',self.setRef.Pawns[self.index - 8usize]),
-            13 => Piece::new('k',self.setRef.King),
-            14 => Piece::new('a',self.setRef.Advisors[0]),
-            15 => Piece::new('a',self.setRef.Advisors[1]),
+         let ret: Piece = match self.index {
+            0 => Piece::new(PieceType::Rook,self.isRed,self.setRef.Rooks[0]),
+            1 => Piece::new(PieceType::Rook,self.isRed,self.setRef.Rooks[1]),
+            2 => Piece::new(PieceType::Cannon,self.isRed,self.setRef.Cannons[0]),
+            3 => Piece::new(PieceType::Cannon,self.isRed,self.setRef.Cannons[1]),
+            4 => Piece::new(PieceType::Horse,self.isRed,self.setRef.Horses[0]),
+            5 => Piece::new(PieceType::Horse,self.isRed,self.setRef.Horses[1]),
+            6 => Piece::new(PieceType::Elephant,self.isRed,self.setRef.Elephants[0]),
+            7 => Piece::new(PieceType::Elephant,self.isRed,self.setRef.Elephants[1]),
+            8..=12 => Piece::new(PieceType::Pawn,self.isRed,self.setRef.Pawns[self.index - 8usize]),
+            13 => Piece::new(PieceType::King,self.isRed,self.setRef.King),
+            14 => Piece::new(PieceType::Advisor,self.isRed,self.setRef.Advisors[0]),
+            15 => Piece::new(PieceType::Advisor,self.isRed,self.setRef.Advisors[1]),
             _ => return None
         };
         if ret.loc == DEAD_PIECE_PACKEDCOORD {
             self.index +=1;
             return self.next(); // Bad to do it this way but it is the cleanest
         }
-        ret.isRed = self.isRed;
         self.index +=1;
         return Some(ret);
     }
@@ -0,0 +1,198 @@
+use super::{BoardState, UndoRecord};
+use super::packedmove::{PackedMove, PackedCoord};
+use super::piece::{Piece, PieceType};
+
+/// What a repeated position means, under the Asian (WXF) rules: a plain repetition is a draw,
+/// but whoever forced it by checking or chasing on every one of their moves has to stop, or lose.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Repetition {
+    Draw,
+    /// Every move this side made in the cycle gave check.
+    PerpetualCheck { byRed : bool },
+    /// Every move this side made in the cycle attacked an undefended piece (or a rook with a horse or cannon).
+    PerpetualChase { byRed : bool }
+}
+
+impl Repetition {
+    ///Whether Red is the one who loses for this, or None if it's a draw.
+    pub fn loserIsRed(&self) -> Option<bool> {
+        return match self {
+            Repetition::Draw => None,
+            Repetition::PerpetualCheck { byRed } | Repetition::PerpetualChase { byRed } => Some(*byRed)
+        };
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum GameResult {
+    RedWins,
+    BlackWins,
+    Draw
+}
+
+impl std::fmt::Display for GameResult {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        return match self {
+            GameResult::RedWins => write!(f, "1-0"),
+            GameResult::BlackWins => write!(f, "0-1"),
+            GameResult::Draw => write!(f, "1/2-1/2")
+        };
+    }
+}
+
+/// The usual no-capture limit: 60 moves each. A limit of 0 turns the rule off altogether.
+pub const DEFAULT_NO_CAPTURE_PLIES : u16 = 120;
+
+/// How many times a position has to have been seen before (on top of the current time) for the game to be adjudicated.
+pub const REPETITIONS_TO_ADJUDICATE : usize = 2;
+
+///Looks back through the moves that led to this position (oldest first, ending with the one just played) for the position itself,
+///and classifies the repetition if it's been seen at least this many times before.
+///The search only needs one earlier occurrence, since it can always repeat again.
+pub fn findRepetition(state : &BoardState, history : &[UndoRecord], earlierOccurrences : usize) -> Option<Repetition> {
+    let key = state.getZobristKey();
+    let mut seen = 0;
+    let mut cycleLength = 0;
+    // Nothing from before the last capture can ever come back
+    for (pliesBack, undo) in history.iter().rev().take(state.halfMoveClock as usize).enumerate() {
+        if undo.zobristKey == key {
+            seen += 1;
+            if seen == 1 {
+                cycleLength = pliesBack + 1;
+            }
+            if seen >= earlierOccurrences {
+                break;
+            }
+        }
+    }
+    if seen == 0 || seen < earlierOccurrences {
+        return None;
+    }
+    return Some(classifyCycle(state, history, cycleLength));
+}
+
+///Whether it's been long enough since the last capture for the game to be a draw.
+pub fn isNoCaptureDraw(state : &BoardState, noCapturePlies : u16) -> bool {
+    return noCapturePlies > 0 && state.halfMoveClock >= noCapturePlies;
+}
+
+///Decides whether the game is over, and if so why: no legal moves (which loses, stalemate or not),
+///a repeated position, or too long without a capture. The history is the moves that led here, as findRepetition wants them.
+pub fn adjudicate(state : &BoardState, history : &[UndoRecord], noCapturePlies : u16) -> Option<(GameResult, &'static str)> {
+    if state.getLegalMoves().is_empty() {
+        if state.isRedTurn {
+            return Some((GameResult::BlackWins, "Black wins"));
+        }
+        return Some((GameResult::RedWins, "Red wins"));
+    }
+    let Some(repetition) = findRepetition(state, history, REPETITIONS_TO_ADJUDICATE) else {
+        if isNoCaptureDraw(state, noCapturePlies) {
+            return Some((GameResult::Draw, "Draw by the no-capture rule"));
+        }
+        return None;
+    };
+    return match repetition {
+        Repetition::Draw => Some((GameResult::Draw, "Draw by repetition")),
+        Repetition::PerpetualCheck { byRed : true } => Some((GameResult::BlackWins, "Red checked perpetually")),
+        Repetition::PerpetualCheck { byRed : false } => Some((GameResult::RedWins, "Black checked perpetually")),
+        Repetition::PerpetualChase { byRed : true } => Some((GameResult::BlackWins, "Red chased perpetually")),
+        Repetition::PerpetualChase { byRed : false } => Some((GameResult::RedWins, "Black chased perpetually"))
+    };
+}
+
+///Replays the last cycleLength moves, keeping track of which side checked or chased with every single one of its moves.
+fn classifyCycle(state : &BoardState, history : &[UndoRecord], cycleLength : usize) -> Repetition {
+    let cycle = &history[history.len() - cycleLength..];
+    let mut replay = state.clone();
+    for undo in cycle.iter().rev() {
+        replay.unmake_move(*undo);
+    }
+    // Indexed by whether it's Red's
+    let mut alwaysChecked = [true, true];
+    let mut alwaysChased = [true, true];
+    for undo in cycle {
+        let isRed = replay.isRedTurn;
+        let before = replay.clone();
+        replay.make_move(undo.playedMove);
+        if !replay.isInCheck() {
+            alwaysChecked[isRed as usize] = false;
+        }
+        if !isChase(&before, &replay, undo.playedMove) {
+            alwaysChased[isRed as usize] = false;
+        }
+    }
+    return match (alwaysChecked[1], alwaysChecked[0]) {
+        (true, false) => Repetition::PerpetualCheck { byRed : true },
+        (false, true) => Repetition::PerpetualCheck { byRed : false },
+        (true, true) => Repetition::Draw,
+        (false, false) => match (alwaysChased[1], alwaysChased[0]) {
+            (true, false) => Repetition::PerpetualChase { byRed : true },
+            (false, true) => Repetition::PerpetualChase { byRed : false },
+            _ => Repetition::Draw
+        }
+    };
+}
+
+///Whether the move from before to after threatens to win something that wasn't already threatened: a piece that isn't defended,
+///or a rook, if it's a horse or cannon doing the attacking. That's anything the piece that moved now attacks, plus anything
+///a piece behind it now gets to attack (a discovered chase). Kings and pawns are allowed to chase all they want,
+///and neither kings nor uncrossed pawns count as being chased.
+fn isChase(before : &BoardState, after : &BoardState, playedMove : PackedMove) -> bool {
+    if !chasedFrom(after, playedMove.end()).is_empty() {
+        return true;
+    }
+    let chaserIsRed = !after.isRedTurn;
+    for piece in after.IteratePieces(chaserIsRed) {
+        if piece.loc == playedMove.end() {
+            continue;
+        }
+        // Only the pieces whose lines the move opened up can have anything new to attack
+        if after.getPieceMoves(&piece, Vec::with_capacity(17)) == before.getPieceMoves(&piece, Vec::with_capacity(17)) {
+            continue;
+        }
+        let alreadyChased = chasedFrom(before, piece.loc);
+        if chasedFrom(after, piece.loc).iter().any(|target| !alreadyChased.contains(target)) {
+            return true;
+        }
+    }
+    return false;
+}
+
+///The squares of everything the piece on this square is chasing, in the sense isChase means.
+fn chasedFrom(state : &BoardState, square : PackedCoord) -> Vec<PackedCoord> {
+    let mut ret = Vec::new();
+    let attackerIndex = state.squares[square.y()][square.x()].pieceIndex;
+    let Some(attackerType) = attackerIndex.pieceType() else {
+        return ret;
+    };
+    if matches!(attackerType, PieceType::King | PieceType::Pawn) {
+        return ret;
+    }
+    let attacker = Piece::new(attackerType, attackerIndex.isRed(), square);
+    for target in state.getPieceMoves(&attacker, Vec::with_capacity(17)) {
+        let Some(targetType) = state.squares[target.y()][target.x()].pieceIndex.pieceType() else {
+            continue;
+        };
+        let crossedRiver = if attacker.isRed { target.y() <= super::RED_RIVER } else { target.y() >= super::BLACK_RIVER };
+        if targetType == PieceType::King || (targetType == PieceType::Pawn && !crossedRiver) {
+            continue;
+        }
+        let capture = PackedMove::new_from_packed(square, target);
+        let mut scratch = state.clone();
+        scratch.isRedTurn = attacker.isRed; // Pretend it's the chaser's move again
+        scratch.make_move(capture);
+        if scratch.isKingAttacked(attacker.isRed) { // Can't actually take it
+            continue;
+        }
+        if targetType == PieceType::Rook && matches!(attackerType, PieceType::Horse | PieceType::Cannon) {
+            ret.push(target);
+            continue;
+        }
+        let defended = scratch.getAllMoves().into_iter()
+            .any(|recapture| recapture.end() == target && scratch.isLegalMove(recapture));
+        if !defended {
+            ret.push(target);
+        }
+    }
+    return ret;
+}
@@ -1,27 +1,61 @@
 use super::TileGrid;
+use super::piece::PieceType;
 
 
-#[derive(Clone, PartialEq, Eq, Hash)]
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
 pub struct Tile
 {
     pub pieceIndex : PieceIndex
 }
 
-const NO_PIECE : PieceIndex = PieceIndex::new('\0');
+const NO_PIECE : PieceIndex = PieceIndex { code : 0 };
+const RED_BIT : u8 = 0b1000;
+const TYPE_BITS : u8 = 0b0111;
 
-#[derive(Clone, PartialEq, Eq, Hash)]
+/// A whole piece in one byte. Only turned back into a FEN character when something needs printing. <br/>
+/// 0-2 : the PieceType's discriminant, or 0 for no piece at all <br/>
+/// 3   : set if the piece is Red's
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
 pub struct PieceIndex {
-    pub cara : char // SO DUMB
+    pub code : u8
 }
 
 impl PieceIndex {
-    pub const fn new(c : char) -> Self {
+    pub const fn new(pieceType : PieceType, isRed : bool) -> Self {
         return Self {
-            cara : c
+            code : pieceType as u8 | if isRed { RED_BIT } else { 0 }
         };
     }
+    pub const fn new_from_char(cara : char) -> Option<Self> {
+        return match PieceType::new_from_char(cara) {
+            Some((pieceType, isRed)) => Some(PieceIndex::new(pieceType, isRed)),
+            None => None
+        };
+    }
+    pub const fn pieceType(&self) -> Option<PieceType> {
+        return PieceType::new_from_code(self.code & TYPE_BITS);
+    }
+    pub const fn isRed(&self) -> bool {
+        return self.code & RED_BIT != 0;
+    }
+    pub const fn isEmpty(&self) -> bool {
+        return self.code == NO_PIECE.code;
+    }
+    ///Returns '\0' if there's no piece.
     pub const fn asChar(&self) -> char {
-        return self.cara;
+        return match self.pieceType() {
+            Some(pieceType) => pieceType.asChar(self.isRed()),
+            None => '\0'
+        };
+    }
+}
+
+impl std::fmt::Debug for PieceIndex {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.isEmpty() {
+            return write!(f, "-");
+        }
+        write!(f, "{}", self.asChar())
     }
 }
 
@@ -34,7 +68,6 @@ pub struct TileIterator<'a> {
 }
 
 impl Tile {
-    #[allow(dead_code)] // things need constructors, Rust, god
     pub const fn new() -> Self {
         return Tile::real_default_because_traits_suck();
     }
@@ -47,14 +80,14 @@ impl Tile {
     }
 
     pub const fn hasPiece(&self) -> bool {
-        return self.pieceIndex.cara != '\0';
+        return !self.pieceIndex.isEmpty();
     }
 
     // FIXME:
     //  mutable references are not allowed in constant functions
     //  see issue #57349 <https://github.com/rust-lang/rust/issues/57349> for more information 
     pub fn take(&mut self) -> PieceIndex {
-        let ret = self.pieceIndex.clone();
+        let ret = self.pieceIndex;
         self.pieceIndex = NO_PIECE;
         return ret;
     }
@@ -0,0 +1,45 @@
+use super::tile::PieceIndex;
+use super::packedmove::PackedCoord;
+
+/// One random key per (PieceIndex code, square), so a position's key is just the XOR of the keys of every piece on it. <br/>
+/// Squares are indexed as y * 9 + x. Codes that don't describe a piece (like 0) have keys too, they just never get used.
+const PIECE_KEYS : [[u64;90];16] = generateKeys();
+
+/// XORed in whenever it's Black's move.
+pub const BLACK_TO_MOVE_KEY : u64 = splitmix64(0x4C49_5955_0000_0000).0;
+
+/// A fixed seed, so that keys are the same from run to run (and build to build), which opening books and the like rely on.
+const SEED : u64 = 0x9E37_79B9_7F4A_7C15;
+
+/// Good enough randomness for this, and usable in a const context (unlike anything in std).
+/// Returns (random number, next state).
+const fn splitmix64(state : u64) -> (u64, u64) {
+    let nextState = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    let mut z = nextState;
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    return (z ^ (z >> 31), nextState);
+}
+
+const fn generateKeys() -> [[u64;90];16] {
+    let mut ret = [[0u64;90];16];
+    let mut state = SEED;
+    let mut code = 0;
+    while code < 16 { // for loops aren't allowed in const fns, sigh
+        let mut square = 0;
+        while square < 90 {
+            let (key, nextState) = splitmix64(state);
+            ret[code][square] = key;
+            state = nextState;
+            square += 1;
+        }
+        code += 1;
+    }
+    return ret;
+}
+
+/// The key for this piece sitting at this coordinate.
+#[inline]
+pub fn pieceKey(pieceIndex : PieceIndex, coord : PackedCoord) -> u64 {
+    return PIECE_KEYS[pieceIndex.code as usize][coord.y() * 9 + coord.x()];
+}
@@ -1,108 +1,318 @@
 pub mod score;
+pub mod transposition;
+pub mod limits;
 
 use std::cmp::Ordering;
-use std::time::Instant;
-use crate::board::BoardState;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering as AtomicOrdering};
+use std::time::{Duration, Instant};
+use crate::board::{BoardState, UndoRecord};
+use crate::board::rules;
+use crate::notation::Notation;
 use crate::board::packedmove::PackedMove;
+use crate::board::piece::PieceType;
 
-use self::score::{ScoreF32, RED_WON,BLACK_WON,INVALID_POS};
+use self::score::ScoreI32;
+use self::transposition::{TranspositionTable, TableEntry, Bound, DEFAULT_HASH_MB};
+use self::limits::{SearchLimits, Deadline, MAX_SEARCH_DEPTH};
+
+/// How many plies into the quiescence search we still look at every way out of check, rather than just captures.
+const QUIESCENCE_EVASION_PLIES : i32 = 4;
+
+/// What forcing a repetition by perpetual check or chase costs in the search. Worse than losing every piece on the board,
+/// but not mate, since repeating once isn't a forfeit yet (that takes REPETITIONS_TO_ADJUDICATE), just a line that leads to one.
+const PERPETUAL_PENALTY : i32 = 20000;
+
+/// Checking the clock isn't free, so we only do it every this many nodes (minus one).
+const TIME_CHECK_MASK : u64 = 1023;
+
+/// What a search came up with, as of the last iteration that finished.
+#[derive(Clone, Debug)]
+pub struct SearchResult {
+    pub bestMove : PackedMove,
+    pub pv : Vec<PackedMove>, // The line the engine expects, starting with bestMove
+    pub score : ScoreI32,
+    pub depth : i32,
+    pub rank : usize, // 1 for the best line, 2 for the next best, and so on (see SearchLimits::multiPv)
+    pub nodes : u64,
+    pub elapsed : Duration
+}
 
 pub struct Engine
 {
-    nodeCount: i32,
+    nodeCount: u64,
     recentMoveList : Vec<PackedMove>, // While we do use Vec here, it is definitely preferable to allocate the singular time.
     startStateIsRed : bool,
+    transpositionTable : TranspositionTable, // Unlike everything else here, this is kept between searches.
+    rootDepth : i32, // The depth the current iteration started at, so we can tell when we're at the root.
+    stopSignal : Arc<AtomicBool>, // Lets another thread call the search off
+    deadline : Option<Deadline>,
+    nodeLimit : Option<u64>,
+    bannedMoves : Vec<PackedMove>, // Only applies at the root
+    history : Vec<UndoRecord>, // The game's moves and then the search's own, down to whatever position we're in, for spotting repetitions
+    pathDependent : bool, // Whether the last score _eval returned hangs on how we got there (a repetition or the no-capture rule), so can't go in the table
+    noCapturePlies : u16, // Positions this many plies past the last capture are draws (0 for never)
+    notation : Notation, // For the moves searchVerbosely prints
+    aborted : bool // Once set, every score is garbage until we're back out at the root
 }
 
 impl Engine {
-    fn new() -> Self {
+    pub fn new() -> Self {
         return Self {
             nodeCount : 0,
             recentMoveList : Default::default(),
-            startStateIsRed : Default::default()
+            startStateIsRed : Default::default(),
+            transpositionTable : TranspositionTable::new(DEFAULT_HASH_MB),
+            rootDepth : 0,
+            stopSignal : Arc::new(AtomicBool::new(false)),
+            deadline : None,
+            nodeLimit : None,
+            bannedMoves : Vec::new(),
+            history : Vec::new(),
+            pathDependent : false,
+            noCapturePlies : rules::DEFAULT_NO_CAPTURE_PLIES,
+            notation : Notation::default(),
+            aborted : false
         };
     }
-    pub fn evalToDepth(startState : &BoardState, depth : i32) -> ScoreF32 {
-        let mut engine : Self = Engine::new();
+
+    ///Setting the returned flag to true makes the current (or next) search wrap up as soon as it can.
+    pub fn stopHandle(&self) -> Arc<AtomicBool> {
+        return self.stopSignal.clone();
+    }
+
+    ///Resizes (and so clears) the transposition table.
+    pub fn setHashSize(&mut self, megabytes : usize) {
+        self.transpositionTable.resize(megabytes);
+    }
+
+    pub fn getHashSize(&self) -> usize {
+        return self.transpositionTable.sizeInMegabytes();
+    }
+
+    ///How many plies without a capture it takes for the game to be drawn, or 0 to play on forever.
+    pub fn setNoCaptureLimit(&mut self, plies : u16) {
+        self.noCapturePlies = plies;
+    }
+
+    pub fn getNoCaptureLimit(&self) -> u16 {
+        return self.noCapturePlies;
+    }
+
+    ///Which notation searchVerbosely writes its moves in.
+    pub fn setNotation(&mut self, notation : Notation) {
+        self.notation = notation;
+    }
+
+    pub fn getNotation(&self) -> Notation {
+        return self.notation;
+    }
+
+    #[allow(dead_code)] // Needed for tests
+    pub fn evalToDepth(startState : &BoardState, depth : i32) -> ScoreI32 {
+        return Engine::new().search(startState, &[], depth);
+    }
+
+    ///Like evalToDepth, but keeps whatever this engine learned from previous searches, and knows how the game got here.
+    pub fn search(&mut self, startState : &BoardState, history : &[UndoRecord], depth : i32) -> ScoreI32 {
+        return self.searchVerbosely(startState, history, &SearchLimits::new_from_depth(depth)).score;
+    }
+
+    ///Searches within the given limits, printing how each iteration went in the usual REPL way.
+    pub fn searchVerbosely(&mut self, startState : &BoardState, history : &[UndoRecord], limits : &SearchLimits) -> SearchResult {
+        let notation = self.notation;
+        return self.searchWithLimits(startState, history, limits, &mut |result| {
+            print!("[{}] Engine evaluated {} nodes ({} nodes/sec)\n", result.depth, result.nodes, (result.nodes as f32) / result.elapsed.as_secs_f32());
+            println!("Preferred move: {}",notation.formatMoveWithCoordinates(startState, result.bestMove));
+        });
+    }
+
+    ///Iterative deepening: searches one ply deeper each time until one of the limits is hit,
+    ///calling report() after every iteration that got to finish. Iterations that get cut off are thrown away.
+    ///The history is the moves that led to startState, as rules::findRepetition wants them, so that the search can see repetitions coming.
+    pub fn searchWithLimits(&mut self, startState : &BoardState, history : &[UndoRecord], limits : &SearchLimits, report : &mut dyn FnMut(&SearchResult)) -> SearchResult {
         let now = Instant::now();
-        let mut ret : ScoreF32 = ScoreF32::new(0.0f32);
-        for i in 1..=depth {
-            engine.recentMoveList.push(PackedMove::new());
-            if i % 2 != depth % 2 {continue;}
-            engine.startStateIsRed = startState.isRedTurn;
-            assert_eq!(engine.recentMoveList.len(),i as usize);
-            ret = engine._eval_first(startState.to_owned(),i);
-            print!("[{}] Engine evaluated {} nodes ({} nodes/sec)\n", i, engine.nodeCount, (engine.nodeCount as f32) / now.elapsed().as_secs_f32());
-            //println!("{} move sets recorded",engine.bestCache.len());
-            println!("Preferred move: {}",engine.recentMoveList[0]);
+        self.nodeCount = 0;
+        self.recentMoveList.clear();
+        self.aborted = false;
+        self.deadline = limits.deadline(startState.isRedTurn, now);
+        self.nodeLimit = limits.nodes;
+        self.startStateIsRed = startState.isRedTurn;
+        self.history = history.to_vec();
+        let maxDepth = limits.depth.unwrap_or(MAX_SEARCH_DEPTH).clamp(1, MAX_SEARCH_DEPTH);
+
+        let mut ret = SearchResult {
+            bestMove : PackedMove::new(),
+            pv : Vec::new(),
+            score : ScoreI32::DRAW,
+            depth : 0,
+            rank : 1,
+            nodes : 0,
+            elapsed : Duration::ZERO
+        };
+        let mut state = startState.clone(); // The one board the whole search plays its moves onto
+        'deepening: for i in 1..=maxDepth {
+            if i > 1 && self.shouldStopIterating() {
+                break;
+            }
+            self.recentMoveList.push(PackedMove::new());
+            assert_eq!(self.recentMoveList.len(),i as usize);
+            // For every line past the first, we search again with the better lines' first moves banned
+            self.bannedMoves = limits.bannedMoves.clone();
+            if !limits.searchMoves.is_empty() { // Everything we weren't told to look at might as well be banned
+                self.bannedMoves.extend(startState.getLegalMoves().into_iter().filter(|packedMove| !limits.searchMoves.contains(packedMove)));
+            }
+            for rank in 1..=limits.multiPv.max(1) {
+                self.recentMoveList[0] = PackedMove::new();
+                let score = self._eval_first(&mut state,i);
+                if self.aborted {
+                    break 'deepening;
+                }
+                let bestMove = self.recentMoveList[0];
+                if rank > 1 && bestMove == PackedMove::new() { // Ran out of moves to rank
+                    break;
+                }
+                let result = SearchResult {
+                    bestMove,
+                    pv : self.principalVariation(startState, bestMove, i as usize),
+                    score,
+                    depth : i,
+                    rank,
+                    nodes : self.nodeCount,
+                    elapsed : now.elapsed()
+                };
+                report(&result);
+                self.bannedMoves.push(bestMove);
+                if rank == 1 {
+                    ret = result;
+                }
+            }
+            // Once we've found a mate no longer than the whole search, we've seen every line that could be shorter
+            if ret.score.mateDistance().is_some_and(|plies| plies as i32 <= i) {
+                break;
+            }
+        }
+        ret.nodes = self.nodeCount;
+        ret.elapsed = now.elapsed();
+        self.stopSignal.store(false, AtomicOrdering::Relaxed);
+        return ret;
+    }
+
+    ///Follows the transposition table's best moves out from the given position, starting with firstMove.
+    ///This is only as good as what's still in the table, so it can come back shorter than the search was deep.
+    fn principalVariation(&self, startState : &BoardState, firstMove : PackedMove, maxLength : usize) -> Vec<PackedMove> {
+        let mut ret = Vec::with_capacity(maxLength);
+        let mut state = startState.clone();
+        let mut seenKeys = vec![state.getZobristKey()];
+        let mut nextMove = firstMove;
+        while ret.len() < maxLength && state.getLegalMoves().contains(&nextMove) {
+            state.make_move(nextMove);
+            ret.push(nextMove);
+            if seenKeys.contains(&state.getZobristKey()) { // Don't go round in circles
+                break;
+            }
+            seenKeys.push(state.getZobristKey());
+            match self.transpositionTable.probe(state.getZobristKey()) {
+                Some(entry) => nextMove = entry.bestMove,
+                None => break
+            }
         }
         return ret;
     }
 
+    ///Throws away everything learned in previous searches, for when a new game starts.
+    pub fn clearHash(&mut self) {
+        self.transpositionTable.clear();
+    }
+
+    ///Whether it's worth starting another iteration at all.
+    fn shouldStopIterating(&self) -> bool {
+        if self.stopSignal.load(AtomicOrdering::Relaxed) {
+            return true;
+        }
+        if self.nodeLimit.is_some_and(|limit| self.nodeCount >= limit) {
+            return true;
+        }
+        return self.deadline.is_some_and(|deadline| Instant::now() >= deadline.soft);
+    }
+
+    ///Checked at every node. The first iteration is always allowed to finish, so that we always have a move to give.
+    fn checkAbort(&mut self) -> bool {
+        if self.aborted {
+            return true;
+        }
+        if self.rootDepth <= 1 {
+            return false;
+        }
+        if self.nodeLimit.is_some_and(|limit| self.nodeCount >= limit) {
+            self.aborted = true;
+        } else if self.nodeCount & TIME_CHECK_MASK == 0 {
+            self.aborted = self.stopSignal.load(AtomicOrdering::Relaxed)
+                || self.deadline.is_some_and(|deadline| Instant::now() >= deadline.hard);
+        }
+        return self.aborted;
+    }
+
     /// Higher value is better.
     /// Super simplistic version of the piece scoring in board.rs.
-    fn capture_priority(cara :char) -> i32 {
-        match cara {
-            'k'|'K' => 999, // Shenzhen I/O moment
-            'r'|'R' => 6,
-            'c'|'C' => 5,
-            'h'|'H' => 4,
-            'e'|'E' => 3,
-            'a'|'A' => 2,
-            'p'|'P' => 1,
-            _ => unreachable!("Can't recognize piece given to capture_priority"),
+    const fn capture_priority(pieceType : PieceType) -> i32 {
+        match pieceType {
+            PieceType::King => 999, // Shenzhen I/O moment
+            PieceType::Rook => 6,
+            PieceType::Cannon => 5,
+            PieceType::Horse => 4,
+            PieceType::Elephant => 3,
+            PieceType::Advisor => 2,
+            PieceType::Pawn => 1,
         }
     }
 
     // Lower value is better.
-    fn move_priority(cara : char) -> i32 {
-        match cara {
-            'k'|'K' => 7,
-            'r'|'R' => 4,
-            'c'|'C' => 1,
-            'h'|'H' => 2,
-            'e'|'E' => 5,
-            'a'|'A' => 6,
-            'p'|'P' => 3,
-            _ => unreachable!("Can't recognize piece given to capture_priority"),
+    const fn move_priority(pieceType : PieceType) -> i32 {
+        match pieceType {
+            PieceType::King => 7,
+            PieceType::Rook => 4,
+            PieceType::Cannon => 1,
+            PieceType::Horse => 2,
+            PieceType::Elephant => 5,
+            PieceType::Advisor => 6,
+            PieceType::Pawn => 3,
         }
     }
 
-    fn check_move_priority(cara : char) -> i32 {
-        match cara {
-            'k'|'K' => 1,
-            'r'|'R' => 3,
-            'c'|'C' => 5,
-            'h'|'H' => 4,
-            'e'|'E' => 6,
-            'a'|'A' => 2,
-            'p'|'P' => 7,
-            _ => unreachable!("Can't recognize piece given to capture_priority"),
+    const fn check_move_priority(pieceType : PieceType) -> i32 {
+        match pieceType {
+            PieceType::King => 1,
+            PieceType::Rook => 3,
+            PieceType::Cannon => 5,
+            PieceType::Horse => 4,
+            PieceType::Elephant => 6,
+            PieceType::Advisor => 2,
+            PieceType::Pawn => 7,
         }
     }
 
     ///Looks at moves A and B and decides which should be evaluated first.
     fn sort_moves(state : &BoardState, inCheck : bool, a : &PackedMove, b : &PackedMove) -> std::cmp::Ordering {
-        let alphaPiece = PackedMove::indexStart(&state.squares, a).pieceIndex.asChar();
-        let betaPiece = PackedMove::indexStart(&state.squares, b).pieceIndex.asChar();
+        let alphaPiece = PackedMove::indexStart(&state.squares, a).pieceIndex.pieceType().expect("Moves must start on a piece");
+        let betaPiece = PackedMove::indexStart(&state.squares, b).pieceIndex.pieceType().expect("Moves must start on a piece");
 
         // Handle capture preferences
-        let betaCapturedPiece = PackedMove::indexEnd(&state.squares, b).pieceIndex.asChar();
-        let alphaCapturedPiece = PackedMove::indexEnd(&state.squares, a).pieceIndex.asChar();
-        if alphaCapturedPiece != '\0' && betaCapturedPiece != '\0' {
-            let alphaCaptureScore = Self::capture_priority(alphaCapturedPiece) - Self::capture_priority(alphaPiece);
-            let betaCaptureScore = Self::capture_priority(betaCapturedPiece) - Self::capture_priority(betaPiece);
-            let comp = betaCaptureScore.cmp(&alphaCaptureScore);
-            if comp != Ordering::Equal {
-                return comp;
+        let betaCapturedPiece = PackedMove::indexEnd(&state.squares, b).pieceIndex.pieceType();
+        let alphaCapturedPiece = PackedMove::indexEnd(&state.squares, a).pieceIndex.pieceType();
+        match (alphaCapturedPiece, betaCapturedPiece) {
+            (Some(alphaCaptured), Some(betaCaptured)) => {
+                let alphaCaptureScore = Self::capture_priority(alphaCaptured) - Self::capture_priority(alphaPiece);
+                let betaCaptureScore = Self::capture_priority(betaCaptured) - Self::capture_priority(betaPiece);
+                let comp = betaCaptureScore.cmp(&alphaCaptureScore);
+                if comp != Ordering::Equal {
+                    return comp;
+                }
             }
-        }
-        // If either is a capture, prefer the capturing move
-        else if alphaCapturedPiece != '\0' {
-            return Ordering::Less;
-        }
-        else if betaCapturedPiece != '\0' {
-            return Ordering::Greater;
+            // If either is a capture, prefer the capturing move
+            (Some(_), None) => return Ordering::Less,
+            (None, Some(_)) => return Ordering::Greater,
+            (None, None) => {}
         }
         if inCheck {
             return Self::check_move_priority(alphaPiece).cmp(&Self::check_move_priority(betaPiece));
@@ -119,119 +329,260 @@ impl Engine {
         self.recentMoveList[(len - depth) as usize] = packedMove;
     }
 
-    fn _eval_first(&mut self, state : BoardState, depth : i32) -> ScoreF32 {
-        return self._eval(state,depth, &INVALID_POS, &INVALID_POS);
+    fn _eval_first(&mut self, state : &mut BoardState, depth : i32) -> ScoreI32 {
+        self.rootDepth = depth;
+        return self._eval(state,depth, ScoreI32::INFINITY, -ScoreI32::INFINITY);
     }
 
-    fn _eval(&mut self, state : BoardState, depth : i32, blackBestAbove : &ScoreF32, redBestAbove : &ScoreF32) -> ScoreF32 {
+    ///Searches the given board in place; it's left exactly as it was found once this returns.
+    ///Whatever comes back once the search has been aborted is meaningless, so check self.aborted before using it.
+    fn _eval(&mut self, state : &mut BoardState, depth : i32, blackBestAbove : ScoreI32, redBestAbove : ScoreI32) -> ScoreI32 {
+        self.pathDependent = false;
+        if self.checkAbort() {
+            return ScoreI32::DRAW;
+        }
+        // Going back to a position we've already been in means it can go round again, so there's no point searching it twice
+        if depth != self.rootDepth {
+            if let Some(repetition) = rules::findRepetition(state, &self.history, 1) {
+                self.pathDependent = true;
+                return match repetition.loserIsRed() {
+                    Some(true) => ScoreI32::new(-PERPETUAL_PENALTY),
+                    Some(false) => ScoreI32::new(PERPETUAL_PENALTY),
+                    None => ScoreI32::DRAW
+                };
+            }
+            if rules::isNoCaptureDraw(state, self.noCapturePlies) {
+                self.pathDependent = true;
+                return ScoreI32::DRAW;
+            }
+        }
         if depth == 0 {
-            let val = state.getValue();
-            return val;
+            return self._quiesce(state, 0, blackBestAbove, redBestAbove);
         }
 
-        if redBestAbove != INVALID_POS && blackBestAbove != INVALID_POS {
-            
-            if redBestAbove == blackBestAbove { // alpha-beta collapse!
-                return *redBestAbove;
+        if redBestAbove == blackBestAbove { // alpha-beta collapse!
+            return redBestAbove;
+        }
+
+        let tableEntry = self.transpositionTable.probe(state.getZobristKey());
+        let mut tableMove = PackedMove::new();
+        if let Some(entry) = tableEntry {
+            tableMove = entry.bestMove;
+            // At the root we always want to search, so that there's a preferred move to show for it
+            if depth != self.rootDepth && entry.depth as i32 >= depth {
+                match entry.bound {
+                    Bound::Exact => return entry.score,
+                    Bound::Lower if entry.score >= blackBestAbove => return entry.score,
+                    Bound::Upper if entry.score <= redBestAbove => return entry.score,
+                    _ => {}
+                }
             }
-            //println!("{} and {}", redBestAbove, blackBestAbove);
-            //debug_assert!(depth != 6 && blackBestAbove <= redBestAbove, "red's best was not higher than black's? {blackBestAbove},{redBestAbove}");
         }
 
         let mut moves = state.getAllMoves();
         if moves.is_empty() { // Current player has no moves (and ergo has lost, either by stalemate or checkmate)
             if state.isRedTurn {
-                return score::BLACK_WON;
+                return ScoreI32::BLACK_WON;
             }
-            return score::RED_WON;
+            return ScoreI32::RED_WON;
         }
 
         let inCheck = state.isInCheck();
 
         moves.sort_unstable_by(|a,b| { // Awkward to wrap this function call in a closure but whaaatever
+            if *a == tableMove {
+                return Ordering::Less;
+            }
+            if *b == tableMove {
+                return Ordering::Greater;
+            }
             if *a == self.recentMoveList[0] {
                 return Ordering::Less;
             }
             if *b == self.recentMoveList[0] {
                 return Ordering::Greater;
             }
-            Self::sort_moves(&state, inCheck, a, b)
+            Self::sort_moves(state, inCheck, a, b)
         });
 
         let mut foundValidMove : bool = false;
-        let mut ourBest : ScoreF32;
+        let mut ourBest : ScoreI32;
         let mut ourBestMove : PackedMove = PackedMove::new();
         if state.isRedTurn {
-            ourBest = BLACK_WON;
+            ourBest = ScoreI32::BLACK_WON;
         } else {
-            ourBest = RED_WON;
+            ourBest = ScoreI32::RED_WON;
         }
+        let mut pathDependent = false; // Whether any of the scores we're choosing between is
 
         for packedMove in moves { // for every possible move
+            if depth == self.rootDepth && self.bannedMoves.contains(&packedMove) {
+                continue;
+            }
             //debug_assert!(here.0 < 9);
             //debug_assert!(here.1 < 10);
-            let newBoard = state.branch(packedMove); // apply it to the board
-            self.nodeCount += 1;
-            let moveScore : ScoreF32;
-            if !newBoard.hasKing() {
-                if state.isRedTurn {
-                    moveScore = RED_WON;
-                } else {
-                    moveScore = BLACK_WON;
-                }
+            let isRedTurn = state.isRedTurn;
+            let undo = state.make_move(packedMove); // apply it to the board
+            if state.isKingAttacked(isRedTurn) { // Moving this left our own king hanging, so it was never really an option
+                state.unmake_move(undo);
+                continue;
             }
-            else {
-                if state.isRedTurn {
-                    moveScore = self._eval(newBoard, depth-1, &blackBestAbove, ourBest.shitty_max(redBestAbove));
-                } else {
-                    moveScore = self._eval(newBoard, depth-1, ourBest.shitty_min(blackBestAbove), &redBestAbove);
-                }
-                foundValidMove = true;
+            self.nodeCount += 1;
+            foundValidMove = true;
+            self.history.push(undo);
+            // Mate scores count plies from the position they're in, so they have to be shifted on the way down and back up
+            let moveScore : ScoreI32;
+            if isRedTurn {
+                moveScore = self._eval(state, depth-1, blackBestAbove.toChild(), ourBest.max(redBestAbove).toChild()).toParent();
+            } else {
+                moveScore = self._eval(state, depth-1, ourBest.min(blackBestAbove).toChild(), redBestAbove.toChild()).toParent();
             }
-            if moveScore == score::INVALID_POS {
-                //state.branch((here,there)).Display();
-                continue;
-                //panic!("Position was invalid:");
+            pathDependent |= self.pathDependent;
+            self.history.pop();
+            state.unmake_move(undo);
+            if self.aborted {
+                break;
             }
             
             if state.isRedTurn { // if current player is red
                 if moveScore > ourBest { // if this move is better than the old best
-                    if moveScore > *blackBestAbove { 
+                    if moveScore >= blackBestAbove { 
                         //If this results in a position so good that black should've just prevented it from happening
                         //then lets say they did.
                         //println!("*snip for Red ({} vs {})",moveScore,blackBestAbove);
-                        return *blackBestAbove;
+                        self.pathDependent = pathDependent;
+                        self.storeEntry(state, depth, moveScore, packedMove, Bound::Lower);
+                        return blackBestAbove;
                     }
                     ourBest = moveScore; // cool :)
                     ourBestMove = packedMove;
                 }
-                if moveScore == score::RED_WON { // if this move just wins then we're done
+                if moveScore == ScoreI32::redMatesIn(1) { // if this move just wins then we're done
                     break;
                 }
                 
             } else { // Current player is black
                 if moveScore < ourBest {
-                    if moveScore < *redBestAbove { 
+                    if moveScore <= redBestAbove { 
                         //If this results in a position so bad that red should've just prevented it from happening
                         //then lets say they did.
                         //println!("*snip for Black ({} vs {})",moveScore,redBestAbove);
-                        return *redBestAbove;
+                        self.pathDependent = pathDependent;
+                        self.storeEntry(state, depth, moveScore, packedMove, Bound::Upper);
+                        return redBestAbove;
                     }
                     ourBest = moveScore;
                     ourBestMove = packedMove;
                 }
-                if moveScore == score::BLACK_WON {
+                if moveScore == ScoreI32::blackMatesIn(1) {
                     break;
                 }
             }
         }
+        if self.aborted { // Everything past the point we stopped was skipped, so none of this can be trusted
+            return ourBest;
+        }
         self.recordRecentMove(ourBestMove, depth);
         if !foundValidMove { // No valid moves means we're checkmated or stalemated, probably
             if state.isRedTurn {
-                return score::BLACK_WON;
+                return ScoreI32::BLACK_WON;
+            }
+            return ScoreI32::RED_WON;
+        }
+        //If nothing beat the bound we were handed, then our children were cut short by it and we only know this much
+        let bound = if state.isRedTurn && ourBest <= redBestAbove {
+            Bound::Upper
+        } else if !state.isRedTurn && ourBest >= blackBestAbove {
+            Bound::Lower
+        } else {
+            Bound::Exact
+        };
+        self.pathDependent = pathDependent;
+        self.storeEntry(state, depth, ourBest, ourBestMove, bound);
+        return ourBest;
+    }
+
+    ///Keeps playing out captures (and getting out of check) past the end of the main search,
+    ///so that we never stop to evaluate a position halfway through an exchange.
+    fn _quiesce(&mut self, state : &mut BoardState, ply : i32, blackBestAbove : ScoreI32, redBestAbove : ScoreI32) -> ScoreI32 {
+        if self.checkAbort() {
+            return ScoreI32::DRAW;
+        }
+        // Past a few plies we stop bothering with check evasions, since checks can go back and forth forever
+        let inCheck = ply < QUIESCENCE_EVASION_PLIES && state.isInCheck();
+        let mut ourBest : ScoreI32;
+        if inCheck { // No standing pat when in check; every option might be terrible
+            if state.isRedTurn {
+                ourBest = ScoreI32::BLACK_WON;
+            } else {
+                ourBest = ScoreI32::RED_WON;
+            }
+        } else {
+            ourBest = state.getValue(); // "Stand pat", the side to move can always just decline to capture anything
+            if state.isRedTurn {
+                if ourBest >= blackBestAbove {
+                    return blackBestAbove;
+                }
+            } else if ourBest <= redBestAbove {
+                return redBestAbove;
+            }
+        }
+
+        let mut moves = state.getAllMoves();
+        if !inCheck {
+            moves.retain(|packedMove| !PackedMove::indexEnd(&state.squares, packedMove).pieceIndex.isEmpty());
+        }
+        moves.sort_unstable_by(|a,b| Self::sort_moves(state, inCheck, a, b)); // Biggest victim, smallest attacker first
+
+        for packedMove in moves {
+            let isRedTurn = state.isRedTurn;
+            let undo = state.make_move(packedMove);
+            if state.isKingAttacked(isRedTurn) {
+                state.unmake_move(undo);
+                continue;
+            }
+            self.nodeCount += 1;
+            let moveScore = if isRedTurn {
+                self._quiesce(state, ply + 1, blackBestAbove.toChild(), ourBest.max(redBestAbove).toChild())
+            } else {
+                self._quiesce(state, ply + 1, ourBest.min(blackBestAbove).toChild(), redBestAbove.toChild())
+            }.toParent();
+            state.unmake_move(undo);
+            if self.aborted {
+                return ourBest;
+            }
+
+            if isRedTurn {
+                if moveScore > ourBest {
+                    if moveScore >= blackBestAbove {
+                        return blackBestAbove;
+                    }
+                    ourBest = moveScore;
+                }
+            } else if moveScore < ourBest {
+                if moveScore <= redBestAbove {
+                    return redBestAbove;
+                }
+                ourBest = moveScore;
             }
-            return score::RED_WON;
         }
+        // If we were in check and nothing got us out of it, then that's mate, which is what ourBest already says
         return ourBest;
     }
+
+    fn storeEntry(&mut self, state : &BoardState, depth : i32, score : ScoreI32, bestMove : PackedMove, bound : Bound) {
+        if depth == self.rootDepth && !self.bannedMoves.is_empty() { // Not the real answer for this position, so don't remember it as one
+            return;
+        }
+        if self.pathDependent { // Might not hold if we got here some other way
+            return;
+        }
+        self.transpositionTable.store(TableEntry {
+            key : state.getZobristKey(),
+            score,
+            bestMove,
+            depth : depth.min(i8::MAX as i32) as i8,
+            bound
+        });
+    }
 }
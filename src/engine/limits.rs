@@ -0,0 +1,165 @@
+use std::time::{Duration, Instant};
+use crate::board::packedmove::PackedMove;
+
+/// How deep iterative deepening will go when nothing else stops it first.
+pub const MAX_SEARCH_DEPTH : i32 = 64;
+
+/// Time we never spend, so that the move still makes it back before the flag falls.
+const MOVE_OVERHEAD : Duration = Duration::from_millis(30);
+
+/// When we don't know how many moves are left until the next time control, assume it's this many.
+const DEFAULT_MOVES_TO_GO : u32 = 30;
+
+/// Everything that can end a search. Whichever limit is hit first wins; a search with no limits at all
+/// runs until MAX_SEARCH_DEPTH or until someone tells it to stop.
+#[derive(Clone, Debug, Default)]
+pub struct SearchLimits {
+    pub depth : Option<i32>,
+    pub moveTime : Option<Duration>,
+    pub nodes : Option<u64>,
+    pub redTime : Option<Duration>,
+    pub blackTime : Option<Duration>,
+    pub redIncrement : Duration,
+    pub blackIncrement : Duration,
+    pub movesToGo : Option<u32>,
+    /// Moves that mustn't be played from the root position, whatever the search thinks of them.
+    pub bannedMoves : Vec<PackedMove>,
+    /// If there are any, the only moves that may be played from the root position (UCI's searchmoves).
+    pub searchMoves : Vec<PackedMove>,
+    /// How many of the best lines to report each iteration, rather than just the best one. 0 counts as 1.
+    pub multiPv : usize
+}
+
+/// When a search has to be done by, worked out once right as it starts.
+#[derive(Clone, Copy, Debug)]
+pub struct Deadline {
+    /// Past this, we don't bother starting another iteration, since it likely wouldn't finish anyways.
+    pub soft : Instant,
+    /// Past this, we drop whatever we're doing.
+    pub hard : Instant
+}
+
+impl SearchLimits {
+    pub fn new_from_depth(depth : i32) -> Self {
+        return Self {
+            depth : Some(depth),
+            ..Default::default()
+        };
+    }
+
+    #[allow(dead_code)] // Needed for tests
+    pub fn new_from_movetime(moveTime : Duration) -> Self {
+        return Self {
+            moveTime : Some(moveTime),
+            ..Default::default()
+        };
+    }
+
+    /// Reads the arguments of a 'go' command, in the usual "key value key value" style.
+    /// Times are in milliseconds, and wtime/winc are Red's (since Red moves first, like White).
+    /// 'searchmoves' takes every move after it, and 'mate N' just limits the depth to what a mate in N needs.
+    pub fn parseGoArguments(words : &[&str]) -> Result<Self,String> {
+        let mut ret = Self::default();
+        let mut i = 0;
+        while i < words.len() {
+            let key = words[i];
+            if key == "infinite" {
+                i += 1;
+                continue;
+            }
+            if key == "searchmoves" {
+                i += 1;
+                while let Some(packedMove) = words.get(i).and_then(|moveStr| PackedMove::new_from_coordString(moveStr)) {
+                    ret.searchMoves.push(packedMove);
+                    i += 1;
+                }
+                continue;
+            }
+            let Some(valueStr) = words.get(i + 1) else {
+                return Err(format!("No value given for '{}'",key));
+            };
+            let Ok(value) = valueStr.parse::<u64>() else {
+                return Err(format!("Value for '{}' must be a positive integer, not '{}'",key,valueStr));
+            };
+            match key {
+                "depth" => {
+                    let depth = value.min(MAX_SEARCH_DEPTH as u64) as i32;
+                    ret.depth = Some(ret.depth.map_or(depth, |mateDepth| mateDepth.min(depth)));
+                }
+                "movetime" => ret.moveTime = Some(Duration::from_millis(value)),
+                "nodes" => ret.nodes = Some(value),
+                "wtime" => ret.redTime = Some(Duration::from_millis(value)),
+                "btime" => ret.blackTime = Some(Duration::from_millis(value)),
+                "winc" => ret.redIncrement = Duration::from_millis(value),
+                "binc" => ret.blackIncrement = Duration::from_millis(value),
+                "movestogo" => ret.movesToGo = Some(u32::try_from(value).map_err(|_| format!("Value for 'movestogo' is too big: {}",value))?),
+                "mate" => {
+                    let matePlies = value.saturating_mul(2).saturating_sub(1).clamp(1, MAX_SEARCH_DEPTH as u64) as i32;
+                    ret.depth = Some(ret.depth.map_or(matePlies, |depth| depth.min(matePlies))); // Whichever of depth and mate is tighter
+                }
+                _ => return Err(format!("Unknown search limit '{}'",key))
+            }
+            i += 2;
+        }
+        return Ok(ret);
+    }
+
+    /// Reads the arguments of a UCCI 'go', which gives clocks as ours and the opponent's rather than by colour,
+    /// in seconds unless 'setoption usemillisec true' said otherwise. Also returns whether the search is infinite.
+    pub fn parseUcciGoArguments(words : &[&str], isRedTurn : bool, useMillisec : bool) -> Result<(Self, bool),String> {
+        let mut ret = Self::default();
+        let mut infinite = false;
+        let mut i = 0;
+        while i < words.len() {
+            let key = words[i];
+            if matches!(key, "ponder" | "draw") { // Handled by the caller, and nothing we care about, respectively
+                i += 1;
+                continue;
+            }
+            if key == "infinite" {
+                infinite = true;
+                i += 1;
+                continue;
+            }
+            let Some(valueStr) = words.get(i + 1) else {
+                return Err(format!("No value given for '{}'",key));
+            };
+            let Ok(value) = valueStr.parse::<u64>() else {
+                return Err(format!("Value for '{}' must be a positive integer, not '{}'",key,valueStr));
+            };
+            let time = if useMillisec { Duration::from_millis(value) } else { Duration::from_secs(value) };
+            match (key, isRedTurn) {
+                ("depth", _) => ret.depth = Some(value.min(MAX_SEARCH_DEPTH as u64) as i32),
+                ("nodes", _) => ret.nodes = Some(value),
+                ("movestogo", _) => ret.movesToGo = Some(u32::try_from(value).map_err(|_| format!("Value for 'movestogo' is too big: {}",value))?),
+                ("oppmovestogo", _) => {}
+                ("time", true) | ("opptime", false) => ret.redTime = Some(time),
+                ("time", false) | ("opptime", true) => ret.blackTime = Some(time),
+                ("increment", true) | ("oppincrement", false) => ret.redIncrement = time,
+                ("increment", false) | ("oppincrement", true) => ret.blackIncrement = time,
+                _ => return Err(format!("Unknown search limit '{}'",key))
+            }
+            i += 2;
+        }
+        return Ok((ret, infinite));
+    }
+
+    /// Works out when a search started now, for the given side, has to stop. None if there's no time limit at all.
+    pub fn deadline(&self, isRedTurn : bool, start : Instant) -> Option<Deadline> {
+        if let Some(moveTime) = self.moveTime {
+            // We were told exactly how long to take, so take all of it
+            return Some(Deadline { soft : start + moveTime, hard : start + moveTime });
+        }
+        let (ourTime, ourIncrement) = if isRedTurn {
+            (self.redTime?, self.redIncrement)
+        } else {
+            (self.blackTime?, self.blackIncrement)
+        };
+        let usable = ourTime.saturating_sub(MOVE_OVERHEAD);
+        let movesToGo = self.movesToGo.unwrap_or(DEFAULT_MOVES_TO_GO).max(1);
+        let budget = (usable / movesToGo + ourIncrement * 3 / 4).min(usable);
+        // We're allowed to overrun the budget a bit to finish an iteration, but never by enough to get in real trouble
+        let hardBudget = (budget * 3).min(usable / 2).max(budget);
+        return Some(Deadline { soft : start + budget / 2, hard : start + hardBudget });
+    }
+}
@@ -19,12 +19,15 @@ const NAN_BASE : u32 = 0b0_11111111_100_00000_00000_00000_00000u32;
 enum ScoreMasks {
     RedWon =        0b0_00000000_010_00000_00000_00000_00000u32,
     BlackWon =      0b0_00000000_001_00000_00000_00000_00000u32,
-    //TODO: Support providing this level of detail during analysis >:/
-    //RedMating =     0b1_00000000_010_00000_00000_00000_00000u32,
-    //BlackMating =   0b1_00000000_001_00000_00000_00000_00000u32,
+    //The mating ones carry how many plies away the mate is in the bottom bits (see MATE_PLIES_MASK)
+    RedMating =     0b1_00000000_010_00000_00000_00000_00000u32,
+    BlackMating =   0b1_00000000_001_00000_00000_00000_00000u32,
     InvalidPos =    0b1_00000000_011_00000_00000_00000_00000u32
 }
 
+/// Where the distance to mate lives in a RedMating/BlackMating score.
+const MATE_PLIES_MASK : u32 = 0xFFFF;
+
 ///<remarks>
 /// So, like, this COULD HAVE used INFINITY and NEG_INFINITY to describe these values
 /// However, doing it this way allows for a distinction between "a floating-point overflow occurred doing evaluation" and "the engine confirmed victory is unavoidable"
@@ -33,10 +36,27 @@ pub const RED_WON : ScoreF32 = ScoreF32::new_from_binary(NAN_BASE | ScoreMasks::
 pub const BLACK_WON : ScoreF32 = ScoreF32::new_from_binary(NAN_BASE | ScoreMasks::BlackWon as u32);
 pub const INVALID_POS : ScoreF32 = ScoreF32::new_from_binary(NAN_BASE | ScoreMasks::InvalidPos as u32);
 
+/// What a won (or lost) position is worth when it has to be written as a plain number of centipawns.
+pub const MATE_CENTIPAWNS : i32 = 30000;
+
 impl ScoreF32 {
     pub const fn new(val : f32) -> Self {
         return Self { data : val };
     }
+    ///Red checkmates in this many plies (counting both sides' moves), if it plays well. 0 plies is RED_WON.
+    pub const fn redMatesIn(plies : u16) -> Self {
+        if plies == 0 {
+            return RED_WON;
+        }
+        return Self::new_from_binary(NAN_BASE | ScoreMasks::RedMating as u32 | plies as u32);
+    }
+    ///Black checkmates in this many plies (counting both sides' moves), if it plays well. 0 plies is BLACK_WON.
+    pub const fn blackMatesIn(plies : u16) -> Self {
+        if plies == 0 {
+            return BLACK_WON;
+        }
+        return Self::new_from_binary(NAN_BASE | ScoreMasks::BlackMating as u32 | plies as u32);
+    }
     pub const fn new_from_binary(val : u32) -> Self {
         unsafe { return Self { data: transmute::<u32,f32>(val)}; }
     }
@@ -44,6 +64,64 @@ impl ScoreF32 {
         unsafe { return transmute::<f32,u32>(self.data); }
     }
 
+    ///How many plies until Red checkmates, if it does.
+    pub fn redMateDistance(&self) -> Option<u16> {
+        if self == RED_WON {
+            return Some(0);
+        }
+        let bits = self.to_bytes();
+        if bits & !MATE_PLIES_MASK == NAN_BASE | ScoreMasks::RedMating as u32 {
+            return Some((bits & MATE_PLIES_MASK) as u16);
+        }
+        return None;
+    }
+    ///How many plies until Black checkmates, if it does.
+    pub fn blackMateDistance(&self) -> Option<u16> {
+        if self == BLACK_WON {
+            return Some(0);
+        }
+        let bits = self.to_bytes();
+        if bits & !MATE_PLIES_MASK == NAN_BASE | ScoreMasks::BlackMating as u32 {
+            return Some((bits & MATE_PLIES_MASK) as u16);
+        }
+        return None;
+    }
+    ///How many plies until somebody checkmates, if anyone does.
+    pub fn mateDistance(&self) -> Option<u16> {
+        return self.redMateDistance().or(self.blackMateDistance());
+    }
+
+    ///The mate in whole moves, the way UCI and XBoard want it: positive when whoever's turn it is gets to deliver it, negative when they're on the receiving end.
+    pub fn mateInMoves(&self, isRedTurn : bool) -> Option<i32> {
+        let plies = self.mateDistance()?;
+        let weWin = self.redMateDistance().is_some() == isRedTurn;
+        if weWin {
+            return Some(plies.div_ceil(2) as i32);
+        }
+        return Some(-((plies / 2) as i32));
+    }
+
+    ///Scores are relative to the position they were found in, so a mate found one move later is one ply further away from here.
+    pub fn toParent(&self) -> Self {
+        if let Some(plies) = self.redMateDistance() {
+            return Self::redMatesIn(plies.saturating_add(1));
+        }
+        if let Some(plies) = self.blackMateDistance() {
+            return Self::blackMatesIn(plies.saturating_add(1));
+        }
+        return *self;
+    }
+    ///The reverse of toParent, for handing bounds down to the next position.
+    pub fn toChild(&self) -> Self {
+        if let Some(plies) = self.redMateDistance() {
+            return Self::redMatesIn(plies.saturating_sub(1));
+        }
+        if let Some(plies) = self.blackMateDistance() {
+            return Self::blackMatesIn(plies.saturating_sub(1));
+        }
+        return *self;
+    }
+
     // there's no lazy max for PartialOrd so we're doing this instead >_>
     pub fn shitty_max<'a>(&'a self, val : &'a ScoreF32) -> &'a Self {
         if self.eq(&INVALID_POS) || self.lt(val) {
@@ -57,6 +135,24 @@ impl ScoreF32 {
         }
         return self;
     }
+
+    ///The score in hundredths of a pawn, from the point of view of whoever's turn it is, for protocols that want an integer.
+    ///Won positions come out as +/-(MATE_CENTIPAWNS - plies to mate).
+    pub fn centipawns(&self, isRedTurn : bool) -> i32 {
+        let forRed = if let Some(plies) = self.redMateDistance() {
+            MATE_CENTIPAWNS - plies as i32
+        } else if let Some(plies) = self.blackMateDistance() {
+            -MATE_CENTIPAWNS + plies as i32
+        } else if self == INVALID_POS {
+            0
+        } else {
+            (self.data * 100.0f32).round() as i32
+        };
+        if isRedTurn {
+            return forRed;
+        }
+        return -forRed;
+    }
 }
 
 impl Default for ScoreF32 {
@@ -82,6 +178,8 @@ impl Debug for ScoreF32 {
             invalid_bytes => write!(f,"INVALID_POS"),
             redwon_bytes => write!(f,"RED_WON"),
             blackwon_bytes => write!(f,"BLACK_WON"),
+            _ if self.redMateDistance().is_some() => write!(f,"redMatesIn({})",self.redMateDistance().unwrap()),
+            _ if self.blackMateDistance().is_some() => write!(f,"blackMatesIn({})",self.blackMateDistance().unwrap()),
             _ => write!(f,"{}",self.data)
         }
     }
@@ -124,6 +222,13 @@ impl Display for ScoreF32 {
         if self == BLACK_WON {
             return write!(f, "Black Wins");
         }
+        // People count mates in their own moves, not plies
+        if let Some(plies) = self.redMateDistance() {
+            return write!(f, "Red mates in {}", plies.div_ceil(2));
+        }
+        if let Some(plies) = self.blackMateDistance() {
+            return write!(f, "Black mates in {}", plies.div_ceil(2));
+        }
         if self == INVALID_POS {
             return write!(f, "Invalid position");
         }
@@ -139,63 +244,164 @@ impl Binary for ScoreF32 {
 }
 
 ///Can't implement Ord because INVALID_POS has no reasonable ordering
-#[allow(non_upper_case_globals)] // go frick yourself Rust, you got me into this mess in the first place
 impl PartialOrd for ScoreF32 {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> { // ordering should be "$self is [blah] to $other"
-        //Weird shitty kludginess here to allow us to use match,
-        //since it erroneously lacks the functionality of supporting user-defined equality.
-        let my_bytes = self.to_bytes();
-        let their_bytes = other.to_bytes();
-        const invalid_bytes : u32 = INVALID_POS.to_bytes();
-        const redwon_bytes : u32 = RED_WON.to_bytes();
-        const blackwon_bytes : u32 = BLACK_WON.to_bytes();
-
-        match my_bytes {
-            invalid_bytes => {
-                return None; // WARNING: This is a null != null situation.
-            }
-            redwon_bytes => {
-                match their_bytes {
-                    invalid_bytes => {
-                        return None;
-                    }
-                    redwon_bytes => {
-                        return Some(Ordering::Equal);
-                    }
-                    _ => { // This actually does mean (intentionally) that RED_WON is greater than INF.
-                        return Some(Ordering::Greater);
-                    }
-                }
-            }
-            blackwon_bytes => {
-                match their_bytes {
-                    invalid_bytes => {
-                        return None;
-                    }
-                    blackwon_bytes => {
-                        return Some(Ordering::Equal);
-                    }
-                    _ => {
-                        return Some(Ordering::Less);
-                    }
-                }
+        if self == INVALID_POS || other == INVALID_POS {
+            return None; // WARNING: This is a null != null situation.
+        }
+        //Sort everything into tiers first: Black mating, then plain numbers, then Red mating.
+        //This does mean (intentionally) that RED_WON is greater than INF.
+        //Within the mating tiers, the quicker mate is the more extreme score, so both sides go for the fastest mate and the slowest loss.
+        let tierOf = |score : &ScoreF32| -> (i32, f32) {
+            if let Some(plies) = score.redMateDistance() {
+                return (1, -(plies as f32));
             }
-            _ => {
-                match their_bytes {
-                    invalid_bytes => {
-                        return None;
-                    }
-                    redwon_bytes => {
-                        return Some(Ordering::Less);
-                    }
-                    blackwon_bytes => {
-                        return Some(Ordering::Greater);
-                    }
-                    _ => {
-                        return self.data.partial_cmp(&other.data);
-                    }
-                }
+            if let Some(plies) = score.blackMateDistance() {
+                return (-1, plies as f32);
             }
+            return (0, score.data);
         };
+        let (myTier, myValue) = tierOf(self);
+        let (theirTier, theirValue) = tierOf(other);
+        if myTier != theirTier {
+            return Some(myTier.cmp(&theirTier));
+        }
+        return myValue.partial_cmp(&theirValue);
     }
-}
\ No newline at end of file
+}
+
+/// The integer alternative to ScoreF32: centipawns from Red's point of view, with mates squeezed in at the very ends.
+/// Unlike ScoreF32 it's totally ordered (and cheap to compare), so it can go straight into sorts and tables.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct ScoreI32 {
+    pub data : i32
+}
+
+/// The longest mate we can describe. Scores within this many centipawns of +/-MATE_CENTIPAWNS are mates, not evaluations.
+const MAX_MATE_PLIES : i32 = 1000;
+
+impl ScoreI32 {
+    /// Red has checkmated Black.
+    pub const RED_WON : Self = Self::new(MATE_CENTIPAWNS);
+    /// Black has checkmated Red.
+    pub const BLACK_WON : Self = Self::new(-MATE_CENTIPAWNS);
+    /// Nobody's ahead, and nobody can get ahead.
+    pub const DRAW : Self = Self::new(0);
+    /// Beyond any real score, for search windows that haven't been narrowed down yet.
+    pub const INFINITY : Self = Self::new(MATE_CENTIPAWNS + 1);
+
+    pub const fn new(centipawns : i32) -> Self {
+        return Self { data : centipawns };
+    }
+    /// An evaluation, kept out of the mate band no matter how lopsided it is.
+    pub const fn new_from_eval(centipawns : i32) -> Self {
+        const LIMIT : i32 = MATE_CENTIPAWNS - MAX_MATE_PLIES - 1;
+        if centipawns > LIMIT {
+            return Self::new(LIMIT);
+        }
+        if centipawns < -LIMIT {
+            return Self::new(-LIMIT);
+        }
+        return Self::new(centipawns);
+    }
+    ///Red checkmates in this many plies (counting both sides' moves), if it plays well. 0 plies is RED_WON.
+    pub const fn redMatesIn(plies : u16) -> Self {
+        let plies = if (plies as i32) < MAX_MATE_PLIES { plies as i32 } else { MAX_MATE_PLIES };
+        return Self::new(MATE_CENTIPAWNS - plies);
+    }
+    ///Black checkmates in this many plies (counting both sides' moves), if it plays well. 0 plies is BLACK_WON.
+    pub const fn blackMatesIn(plies : u16) -> Self {
+        return Self::new(-Self::redMatesIn(plies).data);
+    }
+
+    ///How many plies until Red checkmates, if it does.
+    pub fn redMateDistance(&self) -> Option<u16> {
+        if self.data >= MATE_CENTIPAWNS - MAX_MATE_PLIES && self.data <= MATE_CENTIPAWNS {
+            return Some((MATE_CENTIPAWNS - self.data) as u16);
+        }
+        return None;
+    }
+    ///How many plies until Black checkmates, if it does.
+    pub fn blackMateDistance(&self) -> Option<u16> {
+        return (-*self).redMateDistance();
+    }
+    ///How many plies until somebody checkmates, if anyone does.
+    pub fn mateDistance(&self) -> Option<u16> {
+        return self.redMateDistance().or(self.blackMateDistance());
+    }
+    ///The mate in whole moves, the way UCI and XBoard want it: positive when whoever's turn it is gets to deliver it, negative when they're on the receiving end.
+    pub fn mateInMoves(&self, isRedTurn : bool) -> Option<i32> {
+        return self.toF32().mateInMoves(isRedTurn);
+    }
+    ///The score from the point of view of whoever's turn it is, for protocols that want it that way.
+    pub fn centipawns(&self, isRedTurn : bool) -> i32 {
+        if isRedTurn {
+            return self.data;
+        }
+        return -self.data;
+    }
+
+    ///Scores are relative to the position they were found in, so a mate found one move later is one ply further away from here.
+    pub fn toParent(&self) -> Self {
+        if let Some(plies) = self.redMateDistance() {
+            return Self::redMatesIn(plies.saturating_add(1));
+        }
+        if let Some(plies) = self.blackMateDistance() {
+            return Self::blackMatesIn(plies.saturating_add(1));
+        }
+        return *self;
+    }
+    ///The reverse of toParent, for handing bounds down to the next position.
+    pub fn toChild(&self) -> Self {
+        if let Some(plies) = self.redMateDistance() {
+            return Self::redMatesIn(plies.saturating_sub(1));
+        }
+        if let Some(plies) = self.blackMateDistance() {
+            return Self::blackMatesIn(plies.saturating_sub(1));
+        }
+        return *self;
+    }
+
+    ///None for INVALID_POS, which has no integer equivalent.
+    pub fn new_from_f32(score : ScoreF32) -> Option<Self> {
+        if score == INVALID_POS {
+            return None;
+        }
+        if let Some(plies) = score.redMateDistance() {
+            return Some(Self::redMatesIn(plies));
+        }
+        if let Some(plies) = score.blackMateDistance() {
+            return Some(Self::blackMatesIn(plies));
+        }
+        return Some(Self::new_from_eval(score.centipawns(true)));
+    }
+    pub fn toF32(&self) -> ScoreF32 {
+        if let Some(plies) = self.redMateDistance() {
+            return ScoreF32::redMatesIn(plies);
+        }
+        if let Some(plies) = self.blackMateDistance() {
+            return ScoreF32::blackMatesIn(plies);
+        }
+        return ScoreF32::new(self.data as f32 / 100.0f32);
+    }
+}
+
+impl Display for ScoreI32 {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        return write!(f, "{}", self.toF32()); // Which already knows how to describe mates
+    }
+}
+
+impl Debug for ScoreI32 {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(),Error> {
+        return write!(f, "{:?}", self.toF32());
+    }
+}
+
+/// Swaps whose favour the score is in, mates included.
+impl std::ops::Neg for ScoreI32 {
+    type Output = Self;
+    fn neg(self) -> Self {
+        return Self::new(-self.data);
+    }
+}
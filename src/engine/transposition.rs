@@ -0,0 +1,91 @@
+use crate::board::packedmove::PackedMove;
+use super::score::ScoreI32;
+
+pub const DEFAULT_HASH_MB : usize = 16;
+/// The smallest and largest tables we'll make, in megabytes. These are what we tell GUIs, too.
+pub const MIN_HASH_MB : usize = 1;
+pub const MAX_HASH_MB : usize = 4096;
+
+///Reads a table size in megabytes, giving None if it isn't a number or is out of range.
+pub fn parseHashSize(value : &str) -> Option<usize> {
+    return value.parse::<usize>().ok().filter(|megabytes| (MIN_HASH_MB..=MAX_HASH_MB).contains(megabytes));
+}
+
+/// How a stored score relates to the true value of the position, given that alpha-beta often only proves one side of it.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Bound {
+    /// The score is the position's value (to the stored depth).
+    Exact,
+    /// The position is worth at least this much (to Red); the search stopped early because Black wouldn't allow it.
+    Lower,
+    /// The position is worth at most this much (to Red); the search stopped early because Red wouldn't allow it.
+    Upper
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct TableEntry {
+    pub key : u64,
+    pub score : ScoreI32,
+    pub bestMove : PackedMove,
+    pub depth : i8, // Negative means the slot is empty
+    pub bound : Bound
+}
+
+const EMPTY_ENTRY : TableEntry = TableEntry {
+    key : 0,
+    score : ScoreI32::DRAW,
+    bestMove : PackedMove::new(),
+    depth : -1,
+    bound : Bound::Exact
+};
+
+/// A fixed-size hash table of previously searched positions, keyed by Zobrist key.
+/// Collisions just overwrite each other, unless the old entry was searched deeper.
+pub struct TranspositionTable {
+    entries : Vec<TableEntry>,
+    mask : usize
+}
+
+impl TranspositionTable {
+    pub fn new(megabytes : usize) -> Self {
+        let mut ret = Self {
+            entries : Vec::new(),
+            mask : 0
+        };
+        ret.resize(megabytes);
+        return ret;
+    }
+
+    /// Throws away everything stored and reallocates. Sizes out of range get clamped, then rounded down to a power of two entries.
+    pub fn resize(&mut self, megabytes : usize) {
+        let megabytes = megabytes.clamp(MIN_HASH_MB, MAX_HASH_MB);
+        let wantedEntries = (megabytes * 1024 * 1024 / std::mem::size_of::<TableEntry>()).max(1);
+        let entryCount = 1usize << wantedEntries.ilog2();
+        self.entries = vec![EMPTY_ENTRY; entryCount];
+        self.mask = entryCount - 1;
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.fill(EMPTY_ENTRY);
+    }
+
+    pub fn sizeInMegabytes(&self) -> usize {
+        return self.entries.len() * std::mem::size_of::<TableEntry>() / (1024 * 1024);
+    }
+
+    pub fn probe(&self, key : u64) -> Option<TableEntry> {
+        let entry = self.entries[key as usize & self.mask];
+        if entry.depth < 0 || entry.key != key {
+            return None;
+        }
+        return Some(entry);
+    }
+
+    pub fn store(&mut self, entry : TableEntry) {
+        let slot = &mut self.entries[entry.key as usize & self.mask];
+        if slot.key == entry.key && slot.depth > entry.depth { // Don't throw away a deeper search of the same position
+            return;
+        }
+        *slot = entry;
+    }
+}
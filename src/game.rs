@@ -0,0 +1,115 @@
+use crate::board::{BoardState, UndoRecord};
+use crate::board::fen::FenError;
+use crate::board::packedmove::PackedMove;
+
+/// A game as it was played: where it started, every move since, and the position after each of them.
+/// Taking moves back doesn't forget them, so they can be replayed with redo until a different move gets played instead.
+#[derive(Clone)]
+pub struct Game {
+    startFen : String,
+    moves : Vec<PackedMove>, // Including any that have been undone but not yet replaced
+    positions : Vec<BoardState>, // positions[i] is the position after the first i moves, so there's always one more than there are moves
+    history : Vec<UndoRecord>, // history[i] is what playing moves[i] did, for spotting repetitions
+    current : usize // How many moves in we're looking at
+}
+
+impl Game {
+    pub fn new() -> Self {
+        return Self::new_from_position(BoardState::new());
+    }
+
+    pub fn new_from_fen(fenStr : &str) -> Result<Self,FenError> {
+        return Ok(Self::new_from_position(BoardState::try_from_fen(fenStr)?));
+    }
+
+    pub fn new_from_position(startPosition : BoardState) -> Self {
+        return Self {
+            startFen : startPosition.writeFEN(),
+            moves : Vec::new(),
+            positions : vec![startPosition],
+            history : Vec::new(),
+            current : 0
+        };
+    }
+
+    ///The position we're currently at, which is the end of the game unless moves have been undone.
+    pub fn position(&self) -> &BoardState {
+        return &self.positions[self.current];
+    }
+
+    pub fn startPosition(&self) -> &BoardState {
+        return &self.positions[0];
+    }
+
+    pub fn startFen(&self) -> &str {
+        return &self.startFen;
+    }
+
+    ///The moves leading up to the current position.
+    pub fn moves(&self) -> &[PackedMove] {
+        return &self.moves[..self.current];
+    }
+
+    ///Every position from the start up to the current one, so always one more than there are moves.
+    pub fn positions(&self) -> &[BoardState] {
+        return &self.positions[..=self.current];
+    }
+
+    ///What each of the moves leading up to the current position did, oldest first, as rules::findRepetition wants them.
+    pub fn history(&self) -> &[UndoRecord] {
+        return &self.history[..self.current];
+    }
+
+    ///Every move we know of, including any past the current position that redo would replay.
+    pub fn allMoves(&self) -> &[PackedMove] {
+        return &self.moves;
+    }
+
+    ///How many moves have been played to get to the current position.
+    pub fn currentPly(&self) -> usize {
+        return self.current;
+    }
+
+    ///Plays a move from the current position. If it's the move redo would have played, the moves after it are kept;
+    ///otherwise they're thrown away. Does no legality checking whatsoever, same as BoardState::make_move.
+    pub fn playMove(&mut self, packedMove : PackedMove) {
+        if self.moves.get(self.current) == Some(&packedMove) {
+            self.current += 1;
+            return;
+        }
+        self.moves.truncate(self.current);
+        self.positions.truncate(self.current + 1);
+        self.history.truncate(self.current);
+        let mut newPosition = self.position().clone();
+        let undo = newPosition.make_move(packedMove);
+        self.moves.push(packedMove);
+        self.positions.push(newPosition);
+        self.history.push(undo);
+        self.current += 1;
+    }
+
+    ///Steps back one move, returning the move taken back (or None if we're at the start already).
+    pub fn undo(&mut self) -> Option<PackedMove> {
+        if self.current == 0 {
+            return None;
+        }
+        self.current -= 1;
+        return Some(self.moves[self.current]);
+    }
+
+    ///Replays the next move that was undone, returning it (or None if there's nothing to redo).
+    pub fn redo(&mut self) -> Option<PackedMove> {
+        let packedMove = *self.moves.get(self.current)?;
+        self.current += 1;
+        return Some(packedMove);
+    }
+
+    ///Jumps to the position after the given number of moves. Returns false (and stays put) if there aren't that many.
+    pub fn goto(&mut self, ply : usize) -> bool {
+        if ply > self.moves.len() {
+            return false;
+        }
+        self.current = ply;
+        return true;
+    }
+}
@@ -3,13 +3,22 @@
 
 use std::collections::VecDeque;
 use std::io::{stdin, stdout, Write};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
 
 mod board;
 mod test;
 mod engine;
 mod args;
+mod protocol;
+mod xboard;
+mod game;
+mod notation;
+mod pgn;
+mod xqf;
+mod suite;
 
-use board::packedmove::{PackedCoord, PackedMove};
+use board::packedmove::PackedMove;
 
 use crate::args::parseArgs;
 
@@ -30,6 +39,72 @@ macro_rules! say {
     }
 }
 
+/// Where a session's output goes: stdout, except in tests. Shared with the search thread, which prints its own moves.
+pub type Output = Arc<Mutex<dyn Write + Send>>;
+
+/// Like say!, but to a session's output.
+#[macro_export]
+macro_rules! tell {
+    ($out:expr, $($y:expr),+) => {
+        {
+            let mut out = $out.lock().expect("Nothing panics while writing output");
+            let _ = write!(out, $($y),+);
+            let _ = out.flush();
+        }
+    };
+}
+
+/// Prints the perft count for each legal move in the given position, followed by the total.
+pub fn runPerft(boardPosition : &board::BoardState, depth : u32) {
+    let now = Instant::now();
+    let mut total : u64 = 0;
+    for (packedMove, count) in boardPosition.divide(depth) {
+        say!("{}: {}\n",packedMove,count);
+        total += count;
+    }
+    say!("Total: {} ({} nodes/sec)\n",total,(total as f32) / now.elapsed().as_secs_f32());
+}
+
+/// Explains what's wrong with a move, if anything is.
+fn checkMove(boardPosition : &board::BoardState, packedMove : PackedMove) -> Result<(),String> {
+    let movingPiece = PackedMove::indexStart(&boardPosition.squares, &packedMove).pieceIndex;
+    if movingPiece.isEmpty() {
+        return Err(format!("there's no piece on {}",packedMove.start().coordString()));
+    }
+    if movingPiece.isRed() != boardPosition.isRedTurn {
+        return Err(String::from(if boardPosition.isRedTurn { "it's Red's move" } else { "it's Black's move" }));
+    }
+    if !boardPosition.getLegalMoves().contains(&packedMove) {
+        return Err(String::from("that piece can't move there"));
+    }
+    return Ok(());
+}
+
+/// Prints the game's moves two to a line, numbered the usual way, with a marker after the move that led to the current position.
+pub fn printHistory(game : &game::Game) {
+    let startPly = game.startPosition().plyNumber as usize;
+    let mut line = String::new();
+    for (i, packedMove) in game.allMoves().iter().enumerate() {
+        let ply = startPly + i;
+        if i == 0 || ply.is_multiple_of(2) { // Red's moves start a new line
+            if !line.is_empty() {
+                say!("{}\n",line);
+            }
+            line = format!("{}.",ply / 2 + 1);
+            if ply % 2 == 1 {
+                line.push_str(" ...");
+            }
+        }
+        line.push_str(&format!(" {}",packedMove));
+        if i + 1 == game.currentPly() {
+            line.push_str(" <");
+        }
+    }
+    if !line.is_empty() {
+        say!("{}\n",line);
+    }
+}
+
 fn main() { 
     
 
@@ -38,7 +113,8 @@ fn main() {
     }
 
     println!("Liyu - Version {}",env!("CARGO_PKG_VERSION"));
-    let mut boardPosition : board::BoardState = board::BoardState::new();
+    let mut game = game::Game::new(); // Keeps every move made, so that we can step back through them
+    let mut engine = engine::Engine::new(); // Kept around between evals, so that its transposition table is too
     loop {
         say!("\n> ");
         let mut cmdstr = String::new();
@@ -53,15 +129,32 @@ fn main() {
                 say!("Available commands:\n");
                 say!("'fen [FenString]' - loads in a new position from a valid FEN string.\n");
                 say!("'eval [Depth=6]' - returns the current evaluation of the position.\n");
-                say!("'move [Move]' - plays the given move onto the last saved board\n");
+                say!("'go [depth N] [movetime Ms] [nodes N] [wtime Ms btime Ms] [winc Ms binc Ms] [movestogo N]' - searches until any of the given limits are hit, and gives the best move.\n");
+                say!("'move [Move]' - plays the given move (like h2e2, h2-e2, C2=5 or 炮二平五) onto the last saved board, if it's legal.\n");
+                say!("'undo' / 'redo' - steps back a move, or forward again through moves that were undone.\n");
+                say!("'history' - lists the moves played so far, marking where the current position is.\n");
+                say!("'goto [Ply]' - jumps to the position after the given number of moves (0 for the start).\n");
+                say!("'load [File]' / 'save [File]' - reads a game in from a PGN or XQF file, or writes the moves up to the current position out to one.\n");
+                say!("'perft [Depth=4]' - counts the legal move sequences of the given length, broken down by first move.\n");
+                say!("'hash [MB]' - sets the size of the engine's transposition table, or shows it if no size is given.\n");
+                say!("'nocapture [Plies]' - sets how many plies without a capture draw the game (0 for never), or shows it if none is given.\n");
+                say!("'notation [wxf|iccs|chinese]' - sets which notation moves get shown in, or shows it if none is given.\n");
+                say!("'ucci' / 'uci' / 'xboard' - switches to the UCCI, UCI or XBoard protocol, for talking to GUIs.\n");
                 say!("'display' - displays an ASCII depiction of the current board.\n");
                 say!("'quit' - exits the program.");
             }
             "fen" | "FEN" => {
                 words.pop_front();
                 let fenstr = words.make_contiguous().join(" ");
-                boardPosition = board::BoardState::new_from_FEN(fenstr.as_str());
-                say!("Board position now: {}",boardPosition.writeFEN());
+                match board::BoardState::try_from_fen(fenstr.as_str()) {
+                    Ok(newPosition) => {
+                        game = game::Game::new_from_position(newPosition);
+                        say!("Board position now: {}",game.position().writeFEN());
+                    }
+                    Err(err) => {
+                        say!("{}",err);
+                    }
+                }
             }
             "eval" | "EVAL" => {
                 let depth : i32;
@@ -80,39 +173,200 @@ fn main() {
                         continue;
                     }
                 }
-                say!("Current evaluation: {}",engine::Engine::evalToDepth(&boardPosition, depth));
+                say!("Current evaluation: {}",engine.search(game.position(), game.history(), depth));
+            }
+            "go" | "GO" => {
+                words.pop_front();
+                match engine::limits::SearchLimits::parseGoArguments(words.make_contiguous()) {
+                    Ok(limits) => {
+                        let result = engine.searchVerbosely(game.position(), game.history(), &limits);
+                        say!("Best move: {} ({}, depth {})",engine.getNotation().formatMove(game.position(), result.bestMove),result.score,result.depth);
+                    }
+                    Err(err) => {
+                        say!("Invalid argument to 'go' - {}",err);
+                    }
+                }
+            }
+            "hash" | "HASH" => {
+                match words.len() {
+                    1 => {say!("Transposition table size: {} MB",engine.getHashSize());},
+                    2 => {
+                        let Some(megabytes) = engine::transposition::parseHashSize(words[1]) else {
+                            say!("Invalid argument to 'hash' - argument must be a whole number from {} to {}",engine::transposition::MIN_HASH_MB,engine::transposition::MAX_HASH_MB);
+                            continue;
+                        };
+                        engine.setHashSize(megabytes);
+                        say!("Transposition table size now: {} MB",engine.getHashSize());
+                    }
+                    _ => {
+                        say!("Too many arguments to 'hash'");
+                        continue;
+                    }
+                }
+            }
+            "nocapture" | "NOCAPTURE" => {
+                match words.len() {
+                    1 => {say!("No-capture limit: {} plies",engine.getNoCaptureLimit());},
+                    2 => {
+                        let cmd = words[1].parse::<u16>();
+                        if cmd.is_err() {
+                            say!("Invalid argument to 'nocapture' - argument must be a positive integer");
+                            continue;
+                        }
+                        engine.setNoCaptureLimit(cmd.unwrap());
+                        say!("No-capture limit now: {} plies",engine.getNoCaptureLimit());
+                    }
+                    _ => {
+                        say!("Too many arguments to 'nocapture'");
+                        continue;
+                    }
+                }
+            }
+            "notation" | "NOTATION" => {
+                match words.len() {
+                    1 => {say!("Notation: {}",engine.getNotation());},
+                    2 => {
+                        let Some(newNotation) = notation::Notation::new_from_name(words[1]) else {
+                            say!("Invalid argument to 'notation' - argument must be wxf, iccs or chinese");
+                            continue;
+                        };
+                        engine.setNotation(newNotation);
+                        say!("Notation now: {}",engine.getNotation());
+                    }
+                    _ => {
+                        say!("Too many arguments to 'notation'");
+                        continue;
+                    }
+                }
             }
             "move" | "MOVE" => {
                 match words.len() {
                     1 => {say!("No move given to the 'move' command");},
                     2 => {
-                        // Expecting to move to be in the pattern:
-                        // a1b1
                         let moveStr = words[1];
-                        if moveStr.len() != 4 {
-                            say!("Move is invalid or in an unimplemented format");
+                        // WXF and Chinese moves only read as anything if they're legal, so it's coordinates that get the detailed complaints
+                        let Some(packedMove) = notation::parseAnyMove(game.position(), moveStr) else {
+                            say!("Couldn't read move '{}' - expected coordinates like h2e2 or h2-e2, or a legal WXF or Chinese move like C2=5 or 炮二平五",moveStr);
+                            continue;
+                        };
+                        if let Err(err) = checkMove(game.position(), packedMove) {
+                            say!("Illegal move {}: {}",packedMove.coordString(),err);
                             continue;
                         }
-
-                        let startCoord = PackedCoord::new_from_usize(
-                            (moveStr.bytes().nth(1).unwrap() - b'1').into(),
-                            (moveStr.bytes().nth(0).unwrap() - b'a').into()
-                        );
-                        let endCoord = PackedCoord::new_from_usize(
-                            (moveStr.bytes().nth(3).unwrap() - b'1').into(),
-                            (moveStr.bytes().nth(2).unwrap() - b'a').into()
-                        );
-
-                        let packedMove = PackedMove::new_from_packed(startCoord, endCoord);
-                        say!("Move {} accepted.",packedMove);
-                        boardPosition = boardPosition.branch(packedMove);
-                        
+                        say!("Move {} accepted.",engine.getNotation().formatMoveWithCoordinates(game.position(), packedMove));
+                        game.playMove(packedMove);
+                        if let Some((result, reason)) = board::rules::adjudicate(game.position(), game.history(), engine.getNoCaptureLimit()) {
+                            say!("\nGame over: {} ({})",result,reason);
+                        }
                     },
                     _ => {say!("Too many arguments given to 'move' command");}
                 }
             }
+            "load" | "LOAD" => {
+                words.pop_front();
+                let path = words.make_contiguous().join(" ");
+                if path.is_empty() {
+                    say!("No file given to 'load'");
+                    continue;
+                }
+                let bytes = match std::fs::read(&path) {
+                    Ok(bytes) => bytes,
+                    Err(err) => {
+                        say!("Couldn't read '{}': {}",path,err);
+                        continue;
+                    }
+                };
+                // XQF files are binary, and always start the same way
+                let loaded = if bytes.starts_with(b"XQ") {
+                    xqf::readXqf(&bytes).map(|xqfGame| xqfGame.toGame()).map_err(|err| err.to_string())
+                } else {
+                    pgn::readPgn(&String::from_utf8_lossy(&bytes)).map(|pgnGame| pgnGame.game).map_err(|err| err.to_string())
+                };
+                match loaded {
+                    Ok(loadedGame) => {
+                        game = loadedGame;
+                        say!("Loaded {} moves from '{}'. Board position now: {}",game.currentPly(),path,game.position().writeFEN());
+                    }
+                    Err(err) => {
+                        say!("{}",err);
+                    }
+                }
+            }
+            "save" | "SAVE" => {
+                words.pop_front();
+                let path = words.make_contiguous().join(" ");
+                if path.is_empty() {
+                    say!("No file given to 'save'");
+                    continue;
+                }
+                let result = board::rules::adjudicate(game.position(), game.history(), engine.getNoCaptureLimit()).map(|(result, _)| result);
+                let pgnGame = pgn::PgnGame::new(game.clone(), result);
+                match std::fs::write(&path, pgn::writePgn(&pgnGame, engine.getNotation())) {
+                    Ok(()) => {say!("Saved {} moves to '{}'",game.currentPly(),path);},
+                    Err(err) => {say!("Couldn't write '{}': {}",path,err);}
+                }
+            }
+            "undo" | "UNDO" => {
+                match game.undo() {
+                    Some(packedMove) => {say!("Took back {}.",packedMove);},
+                    None => {say!("Nothing to undo");}
+                }
+            }
+            "redo" | "REDO" => {
+                match game.redo() {
+                    Some(packedMove) => {say!("Replayed {}.",packedMove);},
+                    None => {say!("Nothing to redo");}
+                }
+            }
+            "history" | "HISTORY" => {
+                say!("Started from: {}\n",game.startFen());
+                printHistory(&game);
+            }
+            "goto" | "GOTO" => {
+                match words.len() {
+                    1 => {say!("No ply given to the 'goto' command");},
+                    2 => {
+                        let cmd = words[1].parse::<usize>();
+                        if cmd.is_err() || !game.goto(cmd.unwrap()) {
+                            say!("Invalid argument to 'goto' - argument must be between 0 and {}",game.allMoves().len());
+                            continue;
+                        }
+                        say!("Board position now: {}",game.position().writeFEN());
+                    }
+                    _ => {say!("Too many arguments given to 'goto' command");}
+                }
+            }
+            "perft" | "PERFT" => {
+                let depth : u32;
+                match words.len() {
+                    1 => depth = 4,
+                    2 => {
+                        let cmd = words[1].parse::<u32>();
+                        if cmd.is_err() {
+                            say!("Invalid argument to 'perft' - argument must be a positive integer");
+                            continue;
+                        }
+                        depth = cmd.unwrap();
+                    }
+                    _ => {
+                        say!("Too many arguments to 'perft'");
+                        continue;
+                    }
+                }
+                runPerft(game.position(), depth);
+            }
             "d" | "D" | "display" | "DISPLAY" => {
-                boardPosition.Display();
+                game.position().Display();
+            }
+            "ucci" | "UCCI" | "uci" | "UCI" => {
+                // From here on out we're talking to a GUI rather than a person, until it tells us to quit
+                let chosenProtocol = if words[0].eq_ignore_ascii_case("ucci") { protocol::Protocol::Ucci } else { protocol::Protocol::Uci };
+                protocol::ProtocolSession::new(chosenProtocol, engine).run();
+                break;
+            }
+            "xboard" | "XBOARD" => {
+                xboard::XboardSession::new(engine).run();
+                break;
             }
             "quit" | "q" | "QUIT" => {
                 break;
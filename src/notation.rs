@@ -0,0 +1,145 @@
+pub mod wxf;
+pub mod iccs;
+pub mod chinese;
+
+use crate::board::BoardState;
+use crate::board::packedmove::PackedMove;
+use crate::board::piece::PieceType;
+
+/// The ways of writing moves down that we know, for picking which one to show people.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum Notation {
+    #[default]
+    Wxf,
+    Iccs,
+    Chinese
+}
+
+impl Notation {
+    pub fn new_from_name(name : &str) -> Option<Self> {
+        return match name.to_ascii_lowercase().as_str() {
+            "wxf" => Some(Notation::Wxf),
+            "iccs" | "coordinates" => Some(Notation::Iccs),
+            "chinese" => Some(Notation::Chinese),
+            _ => None
+        };
+    }
+
+    pub fn formatMove(&self, state : &BoardState, packedMove : PackedMove) -> String {
+        return match self {
+            Notation::Wxf => wxf::formatMove(state, packedMove),
+            Notation::Iccs => iccs::formatMove(packedMove),
+            Notation::Chinese => chinese::formatMove(state, packedMove)
+        };
+    }
+
+    ///Like formatMove, but with the coordinates tacked on too when this notation doesn't already spell them out.
+    pub fn formatMoveWithCoordinates(&self, state : &BoardState, packedMove : PackedMove) -> String {
+        if *self == Notation::Iccs {
+            return self.formatMove(state, packedMove);
+        }
+        return format!("{} ({})", self.formatMove(state, packedMove), packedMove.coordString());
+    }
+}
+
+impl std::fmt::Display for Notation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        return match self {
+            Notation::Wxf => write!(f, "wxf"),
+            Notation::Iccs => write!(f, "iccs"),
+            Notation::Chinese => write!(f, "chinese")
+        };
+    }
+}
+
+///Reads a move in whatever notation it turns out to be in: coordinates (ICCS or UCCI style), WXF or Chinese.
+///WXF and Chinese moves only read at all if they're legal; coordinates just have to be on the board.
+pub fn parseAnyMove(state : &BoardState, moveStr : &str) -> Option<PackedMove> {
+    return iccs::parseMove(moveStr)
+        .or_else(|| wxf::parseMove(state, moveStr))
+        .or_else(|| chinese::parseMove(state, moveStr));
+}
+
+/// How a move picks out which piece is moving, in the notations that describe moves from the mover's point of view.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum PieceIdentifier {
+    /// The only piece of its type on this file. Files count 1-9 from the mover's own right.
+    File(usize),
+    /// One of several pieces of its type on the same file, counting from the front (0) back.
+    /// The file only gets mentioned when pawns are doubled up on more than one file, since that's the only time it's needed.
+    Tandem { index : usize, count : usize, file : Option<usize> }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Direction {
+    Forward,
+    Backward,
+    Sideways
+}
+
+/// A move the way Xiangqi players describe it: which piece, which way, and how far or to which file.
+/// Both WXF and Chinese notation are just different ways of writing this down.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct MoveDescription {
+    pub pieceType : PieceType,
+    pub isRed : bool,
+    pub identifier : PieceIdentifier,
+    pub direction : Direction,
+    /// Ranks moved for pieces that move in straight lines (unless moving sideways); otherwise the file moved to.
+    pub amount : usize
+}
+
+///Which file (1-9) this x is, from the point of view of whoever's pieces are in question.
+pub fn fileNumber(x : usize, isRed : bool) -> usize {
+    if isRed {
+        return 9 - x;
+    }
+    return x + 1;
+}
+
+///Describes a move from the given position. Returns None if there's no piece on the starting square (or no move at all).
+pub fn describe(state : &BoardState, packedMove : PackedMove) -> Option<MoveDescription> {
+    if packedMove == PackedMove::new() {
+        return None;
+    }
+    let start = packedMove.start();
+    let end = packedMove.end();
+    let movingPiece = PackedMove::indexStart(&state.squares, &packedMove).pieceIndex;
+    let pieceType = movingPiece.pieceType()?;
+    let isRed = movingPiece.isRed();
+
+    // Every piece of this type that shares a file with another, front ones first
+    let mut sameType : Vec<(usize,usize)> = state.IteratePieces(isRed)
+        .filter(|piece| piece.pieceType == pieceType)
+        .map(|piece| (piece.loc.x(), piece.loc.y()))
+        .collect();
+    sameType.sort_by_key(|&(x, y)| (x, if isRed { 9 - y } else { y }));
+    let countOnFile = |file : usize| sameType.iter().filter(|&&(x, _)| x == file).count();
+    let tandemFiles = (0..9).filter(|&x| countOnFile(x) >= 2).count();
+
+    // Advisors and elephants never need telling apart this way, since where they end up does it for them
+    let count = countOnFile(start.x());
+    let identifier = if count >= 2 && !matches!(pieceType, PieceType::Advisor | PieceType::Elephant) {
+        PieceIdentifier::Tandem {
+            index : sameType.iter().filter(|&&(x, _)| x == start.x()).position(|&(_, y)| y == start.y()).expect("The moving piece has to be in its own set"),
+            count,
+            file : if pieceType == PieceType::Pawn && tandemFiles > 1 { Some(fileNumber(start.x(), isRed)) } else { None }
+        }
+    } else {
+        PieceIdentifier::File(fileNumber(start.x(), isRed))
+    };
+
+    let ranksForward = if isRed { end.y() as i32 - start.y() as i32 } else { start.y() as i32 - end.y() as i32 };
+    let direction = match ranksForward {
+        1.. => Direction::Forward,
+        0 => Direction::Sideways,
+        _ => Direction::Backward
+    };
+    let movesStraight = matches!(pieceType, PieceType::Rook | PieceType::Cannon | PieceType::Pawn | PieceType::King);
+    let amount = if movesStraight && direction != Direction::Sideways {
+        ranksForward.unsigned_abs() as usize
+    } else {
+        fileNumber(end.x(), isRed)
+    };
+    return Some(MoveDescription { pieceType, isRed, identifier, direction, amount });
+}
@@ -0,0 +1,105 @@
+use crate::board::BoardState;
+use crate::board::packedmove::PackedMove;
+use crate::board::piece::PieceType;
+use super::{describe, Direction, MoveDescription, PieceIdentifier};
+
+/// Red writes its numbers as Chinese numerals, Black as (full-width) digits.
+const RED_NUMERALS : [char;9] = ['一','二','三','四','五','六','七','八','九'];
+const BLACK_NUMERALS : [char;9] = ['１','２','３','４','５','６','７','８','９'];
+
+fn pieceCharacter(pieceType : PieceType, isRed : bool) -> char {
+    return match (pieceType, isRed) {
+        (PieceType::King, true) => '帅',
+        (PieceType::King, false) => '将',
+        (PieceType::Advisor, true) => '仕',
+        (PieceType::Advisor, false) => '士',
+        (PieceType::Elephant, true) => '相',
+        (PieceType::Elephant, false) => '象',
+        (PieceType::Horse, _) => '马',
+        (PieceType::Rook, _) => '车',
+        (PieceType::Cannon, _) => '炮',
+        (PieceType::Pawn, true) => '兵',
+        (PieceType::Pawn, false) => '卒'
+    };
+}
+
+fn numeral(number : usize, isRed : bool) -> char {
+    if isRed {
+        return RED_NUMERALS[number - 1];
+    }
+    return BLACK_NUMERALS[number - 1];
+}
+
+/// Chinese notation, like "炮二平五" or "马８进７": the same parts as WXF, in characters.
+/// Doubled-up pieces are 前 (front) and 后 (rear), with 中 for the middle of three, and pawns four or five to a file
+/// are counted 一 to 五 from the front. Those markers go before the piece, and take the place of its file.
+pub fn formatDescription(description : &MoveDescription) -> String {
+    let isRed = description.isRed;
+    let piece = pieceCharacter(description.pieceType, isRed);
+    let mut ret = match description.identifier {
+        PieceIdentifier::File(file) => format!("{}{}", piece, numeral(file, isRed)),
+        PieceIdentifier::Tandem { index, count, file } => {
+            let marker = match (count, index) {
+                (2 | 3, 0) => '前',
+                (3, 1) => '中',
+                (2 | 3, _) => '后',
+                _ => RED_NUMERALS[index]
+            };
+            match file {
+                Some(file) => format!("{}{}{}", marker, piece, numeral(file, isRed)),
+                None => format!("{}{}", marker, piece)
+            }
+        }
+    };
+    ret.push(match description.direction {
+        Direction::Forward => '进',
+        Direction::Backward => '退',
+        Direction::Sideways => '平'
+    });
+    ret.push(numeral(description.amount, isRed));
+    return ret;
+}
+
+///Writes the move in Chinese notation. Gives "????" if there's no piece to move.
+pub fn formatMove(state : &BoardState, packedMove : PackedMove) -> String {
+    return match describe(state, packedMove) {
+        Some(description) => formatDescription(&description),
+        None => String::from("????")
+    };
+}
+
+///Boils a move written in characters down to something that doesn't care about traditional vs simplified characters,
+///which side's piece names were used, or what kind of digits. Only for comparing, never for showing anyone.
+fn canonical(moveStr : &str) -> String {
+    return moveStr.chars().filter(|cara| !cara.is_whitespace()).map(|cara| {
+        if let Some(index) = RED_NUMERALS.iter().position(|numeral| *numeral == cara) {
+            return char::from(b'1' + index as u8);
+        }
+        if let Some(index) = BLACK_NUMERALS.iter().position(|numeral| *numeral == cara) {
+            return char::from(b'1' + index as u8);
+        }
+        return match cara {
+            '帅' | '帥' | '将' | '將' => 'K',
+            '仕' | '士' => 'A',
+            '相' | '象' => 'E',
+            '马' | '馬' | '傌' | '䭾' => 'H',
+            '车' | '車' | '俥' => 'R',
+            '炮' | '砲' => 'C',
+            '兵' | '卒' => 'P',
+            '进' | '進' => '+',
+            '退' => '-',
+            '平' => '=',
+            '前' => 'f',
+            '中' => 'm',
+            '后' | '後' => 'r',
+            other => other
+        };
+    }).collect();
+}
+
+///Reads a move in Chinese notation, which has to be legal in the given position.
+///Traditional characters, either side's piece names and any kind of digits are all fine.
+pub fn parseMove(state : &BoardState, moveStr : &str) -> Option<PackedMove> {
+    let wanted = canonical(moveStr);
+    return state.getLegalMoves().into_iter().find(|packedMove| canonical(&formatMove(state, *packedMove)) == wanted);
+}
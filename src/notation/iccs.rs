@@ -0,0 +1,15 @@
+use crate::board::packedmove::PackedMove;
+
+/// ICCS (Internet Chinese Chess Server) notation, like "H2-E2": the starting and ending squares,
+/// files A-I from Red's left and ranks 0-9 from Red's side. The same squares UCCI uses, just dressed differently.
+pub fn formatMove(packedMove : PackedMove) -> String {
+    if packedMove == PackedMove::new() {
+        return String::from("????");
+    }
+    return format!("{}-{}", packedMove.start().coordString(), packedMove.end().coordString()).to_ascii_uppercase();
+}
+
+///Reads an ICCS move, with or without the dash and in either case. Doesn't check legality.
+pub fn parseMove(moveStr : &str) -> Option<PackedMove> {
+    return PackedMove::new_from_coordString(moveStr.trim());
+}
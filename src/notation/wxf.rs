@@ -0,0 +1,65 @@
+use crate::board::BoardState;
+use crate::board::packedmove::PackedMove;
+use crate::board::piece::PieceType;
+use super::{describe, Direction, MoveDescription, PieceIdentifier};
+
+fn pieceLetter(pieceType : PieceType) -> char {
+    return match pieceType {
+        PieceType::King => 'K',
+        PieceType::Advisor => 'A',
+        PieceType::Elephant => 'E',
+        PieceType::Horse => 'H',
+        PieceType::Rook => 'R',
+        PieceType::Cannon => 'C',
+        PieceType::Pawn => 'P'
+    };
+}
+
+/// WXF (World Xiangqi Federation) notation, like "C2=5" or "H8+7": piece, file, direction, then ranks moved or file moved to.
+/// Two pieces on the same file are told apart with + (front) and - (rear) in place of the file, as in "R+=4".
+/// Pawns three or more to a file are numbered from the front instead, as in "2P=4",
+/// and if pawns are doubled on more than one file, the file gets added back after the marker, as in "P+5=4".
+pub fn formatDescription(description : &MoveDescription) -> String {
+    let letter = pieceLetter(description.pieceType);
+    let mut ret = match description.identifier {
+        PieceIdentifier::File(file) => format!("{}{}", letter, file),
+        PieceIdentifier::Tandem { index, count : 2, .. } => format!("{}{}", letter, if index == 0 { '+' } else { '-' }),
+        PieceIdentifier::Tandem { index, .. } => format!("{}{}", index + 1, letter)
+    };
+    if let PieceIdentifier::Tandem { file : Some(file), .. } = description.identifier {
+        ret.push_str(&file.to_string());
+    }
+    ret.push(match description.direction {
+        Direction::Forward => '+',
+        Direction::Backward => '-',
+        Direction::Sideways => '='
+    });
+    ret.push_str(&description.amount.to_string());
+    return ret;
+}
+
+///Writes the move in WXF notation. Gives "????" if there's no piece to move.
+pub fn formatMove(state : &BoardState, packedMove : PackedMove) -> String {
+    return match describe(state, packedMove) {
+        Some(description) => formatDescription(&description),
+        None => String::from("????")
+    };
+}
+
+///Reads a WXF move, which has to be legal in the given position.
+///Takes the usual variations: any case, '.' for '=', N & B for horses & elephants, and the front/rear marker before the letter ("+C=5").
+pub fn parseMove(state : &BoardState, moveStr : &str) -> Option<PackedMove> {
+    let mut normalized : Vec<char> = moveStr.trim().chars().map(|cara| match cara.to_ascii_uppercase() {
+        '.' => '=',
+        'N' => 'H',
+        'B' => 'E',
+        'G' => 'K',
+        other => other
+    }).collect();
+    if normalized.len() >= 2 && matches!(normalized[0], '+' | '-') && normalized[1].is_ascii_alphabetic() {
+        normalized.swap(0, 1);
+    }
+    let normalized : String = normalized.into_iter().collect();
+    // Every legal move has exactly one way of being written, so whichever one matches is the one
+    return state.getLegalMoves().into_iter().find(|packedMove| formatMove(state, *packedMove) == normalized);
+}
@@ -0,0 +1,248 @@
+use std::fmt::{Display, Formatter};
+
+use crate::board::BoardState;
+use crate::board::fen::FenError;
+use crate::board::rules::GameResult;
+use crate::game::Game;
+use crate::notation::{self, Notation};
+
+/// How long a line of moves gets before we wrap it, as most PGN writers do.
+const LINE_LENGTH : usize = 80;
+
+/// A game file in the Xiangqi dialect of PGN: tag pairs, then the moves.
+/// Only the main line gets kept. Variations are read past, and comments are kept along with the move they come after.
+pub struct PgnGame {
+    /// Every tag besides the ones that get written from the game itself (FEN, Result, Variant and Format), in order.
+    pub tags : Vec<(String,String)>,
+    pub game : Game,
+    /// None for "*", the game being unfinished (or its result unknown).
+    pub result : Option<GameResult>,
+    /// Comments, each with how many moves in it comes after (0 for before the first move).
+    pub comments : Vec<(usize,String)>
+}
+
+/// What readPgn found wrong with a game.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PgnError {
+    /// A line starting with '[' that isn't a tag like [Name "Value"].
+    BadTag(String),
+    /// The FEN tag didn't hold a valid position.
+    BadFen(FenError),
+    /// A move (given by how many moves came before it) that couldn't be read, or isn't legal where it was played.
+    BadMove(usize, String),
+    /// A comment in braces that never got closed.
+    UnterminatedComment,
+    /// A variation's parentheses don't pair up.
+    UnbalancedVariation,
+}
+
+impl Display for PgnError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        return match self {
+            PgnError::BadTag(line) => write!(f, "Invalid PGN: tag not recognized: {}", line),
+            PgnError::BadFen(err) => write!(f, "Invalid PGN: bad FEN tag ({})", err),
+            PgnError::BadMove(ply, moveStr) => write!(f, "Invalid PGN: move {} ('{}') can't be read or isn't legal", ply + 1, moveStr),
+            PgnError::UnterminatedComment => write!(f, "Invalid PGN: a comment is missing its closing brace"),
+            PgnError::UnbalancedVariation => write!(f, "Invalid PGN: a variation's parentheses don't match up"),
+        };
+    }
+}
+
+impl std::error::Error for PgnError {}
+
+impl PgnGame {
+    ///Wraps up a game for saving, with the usual roster of tags left blank for people to fill in.
+    pub fn new(game : Game, result : Option<GameResult>) -> Self {
+        let tags = [("Event", "?"), ("Site", "?"), ("Date", "????.??.??"), ("Round", "?"), ("Red", "?"), ("Black", "?")];
+        return Self {
+            tags : tags.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect(),
+            game,
+            result,
+            comments : Vec::new()
+        };
+    }
+}
+
+fn formatName(notation : Notation) -> &'static str {
+    return match notation {
+        Notation::Wxf => "WXF",
+        Notation::Iccs => "ICCS",
+        Notation::Chinese => "Chinese"
+    };
+}
+
+fn parseResult(token : &str) -> Option<Option<GameResult>> {
+    return match token {
+        "1-0" => Some(Some(GameResult::RedWins)),
+        "0-1" => Some(Some(GameResult::BlackWins)),
+        "1/2-1/2" => Some(Some(GameResult::Draw)),
+        "*" => Some(None),
+        _ => None
+    };
+}
+
+///Reads [Name "Value"], undoing any escaped quotes or backslashes in the value.
+fn parseTag(line : &str) -> Option<(String,String)> {
+    let inner = line.strip_prefix('[')?.strip_suffix(']')?.trim();
+    let (name, value) = inner.split_once(char::is_whitespace)?;
+    let value = value.trim().strip_prefix('"')?.strip_suffix('"')?;
+    return Some((name.to_string(), value.replace("\\\"", "\"").replace("\\\\", "\\")));
+}
+
+///Reads the first game in a PGN file. Moves can be in ICCS, WXF or Chinese notation (or plain coordinates),
+///whatever the Format tag says, and have to be legal.
+pub fn readPgn(text : &str) -> Result<PgnGame, PgnError> {
+    let mut tags = Vec::new();
+    let mut fen = None;
+    let mut result = None;
+    let mut movetext = String::new();
+    for line in text.lines() {
+        let trimmed = line.trim();
+        if trimmed.starts_with('[') {
+            if !movetext.trim().is_empty() {
+                break; // The next game's tags
+            }
+            let (name, value) = parseTag(trimmed).ok_or_else(|| PgnError::BadTag(trimmed.to_string()))?;
+            match name.as_str() {
+                "FEN" => fen = Some(value),
+                "Result" => result = parseResult(&value).flatten(),
+                "Variant" | "Format" => {},
+                _ => tags.push((name, value))
+            }
+            continue;
+        }
+        movetext.push_str(line);
+        movetext.push('\n');
+    }
+
+    let mut game = match fen {
+        Some(fen) => Game::new_from_fen(&fen).map_err(PgnError::BadFen)?,
+        None => Game::new()
+    };
+    let mut comments = Vec::new();
+    let mut variationDepth = 0;
+    let mut chars = movetext.chars();
+    let mut token = String::new();
+    loop {
+        let cara = chars.next();
+        if cara.is_none() || cara.is_some_and(|cara| cara.is_whitespace() || "{;()".contains(cara)) {
+            if variationDepth == 0 && !token.is_empty() {
+                readToken(&mut game, &mut result, &token)?;
+            }
+            token.clear();
+        }
+        match cara {
+            None => break,
+            Some('{') => {
+                let mut comment = String::new();
+                let mut closed = false;
+                for cara in chars.by_ref() {
+                    if cara == '}' {
+                        closed = true;
+                        break;
+                    }
+                    comment.push(cara);
+                }
+                if !closed {
+                    return Err(PgnError::UnterminatedComment);
+                }
+                if variationDepth == 0 {
+                    comments.push((game.currentPly(), comment.trim().to_string()));
+                }
+            }
+            Some(';') => {
+                let comment : String = chars.by_ref().take_while(|&cara| cara != '\n').collect();
+                if variationDepth == 0 {
+                    comments.push((game.currentPly(), comment.trim().to_string()));
+                }
+            }
+            Some('(') => variationDepth += 1,
+            Some(')') => {
+                if variationDepth == 0 {
+                    return Err(PgnError::UnbalancedVariation);
+                }
+                variationDepth -= 1;
+            }
+            Some(cara) if cara.is_whitespace() => {},
+            Some(cara) => token.push(cara)
+        }
+    }
+    if variationDepth != 0 {
+        return Err(PgnError::UnbalancedVariation);
+    }
+    return Ok(PgnGame { tags, game, result, comments });
+}
+
+///Deals with one whitespace-separated piece of the main line: a move, a move number, an annotation or the result.
+fn readToken(game : &mut Game, result : &mut Option<GameResult>, token : &str) -> Result<(), PgnError> {
+    if let Some(tokenResult) = parseResult(token) {
+        *result = tokenResult;
+        return Ok(());
+    }
+    if token.starts_with('$') {
+        return Ok(()); // Numeric annotation glyph
+    }
+    // Move numbers like "12." or "12...", possibly stuck to the move after them. WXF pawns like "2P=4" start with a digit too, but not a dot.
+    let digits = token.len() - token.trim_start_matches(|cara : char| cara.is_ascii_digit()).len();
+    let moveStr = if digits > 0 && token[digits..].starts_with('.') {
+        token[digits..].trim_start_matches('.')
+    } else {
+        token
+    };
+    let moveStr = moveStr.trim_end_matches(['!', '?']);
+    if moveStr.is_empty() {
+        return Ok(());
+    }
+    let position = game.position();
+    let packedMove = notation::parseAnyMove(position, moveStr)
+        .filter(|packedMove| position.getLegalMoves().contains(packedMove))
+        .ok_or_else(|| PgnError::BadMove(game.currentPly(), moveStr.to_string()))?;
+    game.playMove(packedMove);
+    return Ok(());
+}
+
+///Writes the game up to its current position as PGN, with the moves in the given notation.
+pub fn writePgn(pgnGame : &PgnGame, notation : Notation) -> String {
+    let mut ret = String::new();
+    let resultStr = match pgnGame.result {
+        Some(result) => result.to_string(),
+        None => String::from("*")
+    };
+    let ownTags = [("Variant", "Xiangqi"), ("Result", resultStr.as_str()), ("FEN", pgnGame.game.startFen()), ("Format", formatName(notation))];
+    for (name, value) in pgnGame.tags.iter().map(|(name, value)| (name.as_str(), value.as_str())).chain(ownTags) {
+        ret.push_str(&format!("[{} \"{}\"]\n", name, value.replace('\\', "\\\\").replace('"', "\\\"")));
+    }
+    ret.push('\n');
+
+    let mut tokens = Vec::new();
+    let commentsAfter = |ply : usize| pgnGame.comments.iter().filter(move |(commentPly, _)| *commentPly == ply).map(|(_, comment)| format!("{{{}}}", comment));
+    tokens.extend(commentsAfter(0));
+    let positions = pgnGame.game.positions();
+    for (ply, packedMove) in pgnGame.game.moves().iter().enumerate() {
+        let position : &BoardState = &positions[ply];
+        let moveNumber = position.plyNumber / 2 + 1;
+        if position.isRedTurn {
+            tokens.push(format!("{}.", moveNumber));
+        } else if ply == 0 || tokens.last().is_some_and(|token| token.starts_with('{')) {
+            tokens.push(format!("{}...", moveNumber));
+        }
+        tokens.push(notation.formatMove(position, *packedMove));
+        tokens.extend(commentsAfter(ply + 1));
+    }
+    tokens.push(resultStr.clone());
+
+    let mut lineLength = 0;
+    for token in tokens {
+        if lineLength > 0 && lineLength + 1 + token.chars().count() > LINE_LENGTH {
+            ret.push('\n');
+            lineLength = 0;
+        } else if lineLength > 0 {
+            ret.push(' ');
+            lineLength += 1;
+        }
+        lineLength += token.chars().count();
+        ret.push_str(&token);
+    }
+    ret.push('\n');
+    return ret;
+}
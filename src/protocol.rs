@@ -0,0 +1,359 @@
+use std::io::{stdin, stdout};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::board::{BoardState, UndoRecord};
+use crate::board::packedmove::PackedMove;
+use crate::engine::{Engine, SearchResult};
+use crate::engine::limits::SearchLimits;
+use crate::engine::transposition::{self, DEFAULT_HASH_MB, MIN_HASH_MB, MAX_HASH_MB};
+use crate::board::rules::DEFAULT_NO_CAPTURE_PLIES;
+use crate::{tell, Output};
+
+/// The GUI protocols we speak. They're close enough to share a command loop; mostly they differ in the details.
+/// Both use a0-i9 coordinates for moves.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Protocol {
+    /// The Universal Chinese Chess Interface, which most Xiangqi GUIs speak.
+    Ucci,
+    /// Chess' UCI, as used by the Xiangqi tools that never took up UCCI.
+    Uci
+}
+
+/// Everything the protocol front-end has to remember between commands.
+/// The engine itself gets handed off to a thread while it's searching, so that we can keep listening for 'stop'.
+pub struct ProtocolSession {
+    protocol : Protocol,
+    boardPosition : BoardState,
+    history : Vec<UndoRecord>, // The moves from 'position' that got us to boardPosition, so that the search can see repetitions coming
+    engine : Option<Engine>, // None while the search thread has it
+    searchThread : Option<JoinHandle<Engine>>,
+    stopSignal : Arc<AtomicBool>,
+    waitingForStop : Arc<AtomicBool>, // While set, the search thread holds on to its bestmove (pondering, or 'go infinite')
+    ponderLimits : Option<SearchLimits>, // What to switch to once a ponder search gets its ponderhit
+    searchGeneration : Arc<AtomicU64>, // Bumped every 'go', so that a stale timer can't stop the wrong search
+    bannedMoves : Vec<PackedMove>,
+    useMillisec : bool, // UCCI times are in seconds unless the GUI asks otherwise. UCI's are always in milliseconds.
+    multiPv : usize,
+    out : Output
+}
+
+impl ProtocolSession {
+    pub fn new(protocol : Protocol, engine : Engine) -> Self {
+        return Self::new_with_output(protocol, engine, Arc::new(Mutex::new(stdout())));
+    }
+
+    pub fn new_with_output(protocol : Protocol, engine : Engine, out : Output) -> Self {
+        return Self {
+            protocol,
+            boardPosition : BoardState::new(),
+            history : Vec::new(),
+            stopSignal : engine.stopHandle(),
+            engine : Some(engine),
+            searchThread : None,
+            waitingForStop : Arc::new(AtomicBool::new(false)),
+            ponderLimits : None,
+            searchGeneration : Arc::new(AtomicU64::new(0)),
+            bannedMoves : Vec::new(),
+            useMillisec : protocol == Protocol::Uci,
+            multiPv : 1,
+            out
+        };
+    }
+
+    /// Reads commands from stdin until told to quit (or stdin runs dry).
+    pub fn run(&mut self) {
+        match self.protocol { // Whatever got us here was the handshake, so we still owe a reply to it
+            Protocol::Ucci => self.handleCommand("ucci"),
+            Protocol::Uci => self.handleCommand("uci")
+        };
+        loop {
+            let mut cmdstr = String::new();
+            if stdin().read_line(&mut cmdstr).unwrap_or(0) == 0 {
+                self.stopSearch();
+                return;
+            }
+            if !self.handleCommand(cmdstr.trim()) {
+                return;
+            }
+        }
+    }
+
+    /// Returns false once it's time to quit.
+    pub fn handleCommand(&mut self, cmdstr : &str) -> bool {
+        let words : Vec<&str> = cmdstr.split_ascii_whitespace().collect();
+        if words.is_empty() {
+            return true;
+        }
+        match words[0] {
+            "ucci" | "uci" => {
+                tell!(self.out, "id name Liyu {}\n",env!("CARGO_PKG_VERSION"));
+                tell!(self.out, "id author {}\n",env!("CARGO_PKG_AUTHORS"));
+                match self.protocol {
+                    Protocol::Ucci => {
+                        tell!(self.out, "option usemillisec type check default false\n");
+                        tell!(self.out, "option hashsize type spin min {} max {} default {}\n",MIN_HASH_MB,MAX_HASH_MB,DEFAULT_HASH_MB);
+                        tell!(self.out, "option nocapturelimit type spin min 0 max 1000 default {}\n",DEFAULT_NO_CAPTURE_PLIES);
+                        tell!(self.out, "option newgame type button\n");
+                        tell!(self.out, "ucciok\n");
+                    }
+                    Protocol::Uci => {
+                        tell!(self.out, "option name Hash type spin default {} min {} max {}\n",DEFAULT_HASH_MB,MIN_HASH_MB,MAX_HASH_MB);
+                        tell!(self.out, "option name Threads type spin default 1 min 1 max 1\n");
+                        tell!(self.out, "option name MultiPV type spin default 1 min 1 max 128\n");
+                        tell!(self.out, "option name NoCaptureLimit type spin default {} min 0 max 1000\n",DEFAULT_NO_CAPTURE_PLIES);
+                        tell!(self.out, "option name Clear Hash type button\n");
+                        tell!(self.out, "uciok\n");
+                    }
+                }
+            }
+            "isready" => {
+                tell!(self.out, "readyok\n");
+            }
+            "setoption" => {
+                self.stopSearch();
+                self.setOption(&words[1..]);
+            }
+            "position" => {
+                self.stopSearch();
+                self.setPosition(&words[1..]);
+            }
+            "banmoves" => {
+                self.stopSearch();
+                self.bannedMoves.clear();
+                for moveStr in &words[1..] {
+                    match PackedMove::new_from_coordString(moveStr) {
+                        Some(packedMove) => self.bannedMoves.push(packedMove),
+                        None => {tell!(self.out, "info string Ignoring unreadable move {}\n",moveStr);}
+                    }
+                }
+            }
+            "go" => {
+                self.stopSearch();
+                self.go(&words[1..]);
+            }
+            "ucinewgame" => {
+                self.stopSearch();
+                self.engine.as_mut().expect("Engine should be back from searching").clearHash();
+            }
+            "ponderhit" => {
+                self.ponderHit();
+            }
+            "stop" => {
+                self.stopSearch();
+            }
+            "quit" => {
+                self.stopSearch();
+                tell!(self.out, "bye\n");
+                return false;
+            }
+            _ => {} // Both protocols say to just ignore anything we don't understand
+        }
+        return true;
+    }
+
+    /// Takes both "setoption name <Name> value <Value>" (UCI, where names can have spaces) and "setoption <name> <value>" (UCCI).
+    fn setOption(&mut self, words : &[&str]) {
+        let (name, value) = if words.first() == Some(&"name") {
+            let valueIndex = words.iter().position(|word| *word == "value").unwrap_or(words.len());
+            (words[1..valueIndex].join(" "), words.get(valueIndex + 1).copied())
+        } else {
+            match words {
+                [name, value, ..] => (name.to_string(), Some(*value)),
+                [name] => (name.to_string(), None),
+                [] => return
+            }
+        };
+        let engine = self.engine.as_mut().expect("Engine should be back from searching");
+        match (name.to_ascii_lowercase().as_str(), value) {
+            ("usemillisec", Some(value)) => self.useMillisec = value == "true",
+            ("hashsize" | "hash", Some(value)) => match transposition::parseHashSize(value) {
+                Some(megabytes) => engine.setHashSize(megabytes),
+                None => {tell!(self.out, "info string Invalid hash size {} (must be {} to {})\n",value,MIN_HASH_MB,MAX_HASH_MB);}
+            },
+            ("threads", Some(value)) => {
+                if value != "1" {
+                    tell!(self.out, "info string Only one search thread is supported\n");
+                }
+            }
+            ("multipv", Some(value)) => match value.parse::<usize>() {
+                Ok(lines) if lines >= 1 => self.multiPv = lines,
+                _ => {tell!(self.out, "info string Invalid MultiPV {}\n",value);}
+            },
+            ("nocapturelimit", Some(value)) => match value.parse::<u16>() {
+                Ok(plies) => engine.setNoCaptureLimit(plies),
+                Err(_) => {tell!(self.out, "info string Invalid no-capture limit {}\n",value);}
+            },
+            ("newgame" | "clear hash", _) => engine.clearHash(),
+            _ => {tell!(self.out, "info string Unknown option {}\n",name);}
+        }
+    }
+
+    /// position {fen <FEN> | startpos} [moves <move> ...]
+    fn setPosition(&mut self, words : &[&str]) {
+        let movesIndex = words.iter().position(|word| *word == "moves").unwrap_or(words.len());
+        let newPosition = match words.first() {
+            Some(&"startpos") => BoardState::new(),
+            Some(&"fen") => match BoardState::try_from_fen(&words[1..movesIndex].join(" ")) {
+                Ok(newPosition) => newPosition,
+                Err(err) => {
+                    tell!(self.out, "info string {}\n",err);
+                    return;
+                }
+            },
+            _ => {
+                tell!(self.out, "info string Expected 'fen' or 'startpos' after 'position'\n");
+                return;
+            }
+        };
+        self.boardPosition = newPosition;
+        self.history.clear();
+        self.bannedMoves.clear();
+        for moveStr in words.iter().skip(movesIndex + 1) {
+            let packedMove = PackedMove::new_from_coordString(moveStr);
+            match packedMove {
+                Some(packedMove) if self.boardPosition.getLegalMoves().contains(&packedMove) => {
+                    let undo = self.boardPosition.make_move(packedMove);
+                    self.history.push(undo);
+                }
+                _ => {
+                    tell!(self.out, "info string Illegal move {}, ignoring it and everything after it\n",moveStr);
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Turns 'go' arguments into search limits, plus whether the search is meant to go on until we're told to stop.
+    fn parseGo(&self, words : &[&str]) -> Result<(SearchLimits, bool),String> {
+        if self.protocol == Protocol::Uci { // Same as the REPL's, give or take pondering
+            let limitWords : Vec<&str> = words.iter().copied().filter(|word| *word != "ponder").collect();
+            let mut limits = SearchLimits::parseGoArguments(&limitWords)?;
+            limits.bannedMoves = self.bannedMoves.clone();
+            limits.multiPv = self.multiPv;
+            return Ok((limits, words.contains(&"infinite")));
+        }
+        // UCCI describes clocks as "ours" and "theirs" rather than by colour
+        return self.parseUcciGo(words);
+    }
+
+    fn parseUcciGo(&self, words : &[&str]) -> Result<(SearchLimits, bool),String> {
+        let (mut limits, infinite) = SearchLimits::parseUcciGoArguments(words, self.boardPosition.isRedTurn, self.useMillisec)?;
+        limits.bannedMoves = self.bannedMoves.clone();
+        limits.multiPv = self.multiPv;
+        return Ok((limits, infinite));
+    }
+
+    fn go(&mut self, words : &[&str]) {
+        let (limits, infinite) = match self.parseGo(words) {
+            Ok(parsed) => parsed,
+            Err(err) => { // The GUI's still waiting on an answer, so it has to get one
+                tell!(self.out, "info string {}\n",err);
+                match self.protocol {
+                    Protocol::Ucci => {tell!(self.out, "nobestmove\n");}
+                    Protocol::Uci => {tell!(self.out, "bestmove (none)\n");}
+                }
+                return;
+            }
+        };
+        let pondering = words.contains(&"ponder");
+        self.searchGeneration.fetch_add(1, Ordering::Relaxed);
+        self.stopSignal.store(false, Ordering::Relaxed);
+        self.waitingForStop.store(pondering || infinite, Ordering::Relaxed);
+        let searchLimits = if pondering {
+            // Think about it for as long as it takes the opponent to move, then use the real limits from then on
+            self.ponderLimits = Some(limits.clone());
+            SearchLimits { bannedMoves : limits.bannedMoves, multiPv : limits.multiPv, ..Default::default() }
+        } else {
+            limits
+        };
+
+        let mut engine = self.engine.take().expect("Engine should be back from searching");
+        let startState = self.boardPosition.clone();
+        let history = self.history.clone();
+        let stopSignal = self.stopSignal.clone();
+        let waitingForStop = self.waitingForStop.clone();
+        let protocol = self.protocol;
+        let out = self.out.clone();
+        self.searchThread = Some(thread::spawn(move || {
+            let result = engine.searchWithLimits(&startState, &history, &searchLimits, &mut |result| {
+                printInfo(&out, protocol, result, startState.isRedTurn, searchLimits.multiPv > 1);
+            });
+            // We're not allowed to give our answer before being told to, so hang on to it until then
+            while waitingForStop.load(Ordering::Relaxed) && !stopSignal.load(Ordering::Relaxed) {
+                thread::sleep(Duration::from_millis(1));
+            }
+            stopSignal.store(false, Ordering::Relaxed);
+            waitingForStop.store(false, Ordering::Relaxed);
+            printBestMove(&out, protocol, &result);
+            return engine;
+        }));
+    }
+
+    /// The opponent played the move we were pondering on, so the search we already have going becomes the real one.
+    fn ponderHit(&mut self) {
+        let Some(limits) = self.ponderLimits.take() else {
+            return;
+        };
+        if let Some(deadline) = limits.deadline(self.boardPosition.isRedTurn, Instant::now()) {
+            let generation = self.searchGeneration.load(Ordering::Relaxed);
+            let searchGeneration = self.searchGeneration.clone();
+            let stopSignal = self.stopSignal.clone();
+            thread::spawn(move || {
+                thread::sleep(deadline.hard.saturating_duration_since(Instant::now()));
+                if searchGeneration.load(Ordering::Relaxed) == generation {
+                    stopSignal.store(true, Ordering::Relaxed);
+                }
+            });
+        }
+        self.waitingForStop.store(false, Ordering::Relaxed);
+    }
+
+    /// Stops any search that's going (which prints its bestmove), and takes the engine back.
+    fn stopSearch(&mut self) {
+        let Some(searchThread) = self.searchThread.take() else {
+            return;
+        };
+        self.ponderLimits = None;
+        self.waitingForStop.store(false, Ordering::Relaxed);
+        self.stopSignal.store(true, Ordering::Relaxed);
+        self.engine = Some(searchThread.join().expect("Search thread panicked"));
+        self.stopSignal.store(false, Ordering::Relaxed);
+    }
+}
+
+fn printInfo(out : &Output, protocol : Protocol, result : &SearchResult, isRedTurn : bool, showRank : bool) {
+    tell!(out, "info depth {}",result.depth);
+    if showRank {
+        tell!(out, " multipv {}",result.rank);
+    }
+    match protocol {
+        Protocol::Ucci => {tell!(out, " score {}",result.score.centipawns(isRedTurn));}
+        Protocol::Uci => match result.score.mateInMoves(isRedTurn) {
+            Some(moves) => {tell!(out, " score mate {}",moves);}
+            None => {tell!(out, " score cp {}",result.score.centipawns(isRedTurn));}
+        }
+    }
+    tell!(out, " time {} nodes {}",result.elapsed.as_millis(),result.nodes);
+    if !result.pv.is_empty() {
+        let pv : Vec<String> = result.pv.iter().map(|packedMove| packedMove.coordString()).collect();
+        tell!(out, " pv {}",pv.join(" "));
+    }
+    tell!(out, "\n");
+}
+
+fn printBestMove(out : &Output, protocol : Protocol, result : &SearchResult) {
+    if result.bestMove == PackedMove::new() {
+        match protocol {
+            Protocol::Ucci => {tell!(out, "nobestmove\n");}
+            Protocol::Uci => {tell!(out, "bestmove (none)\n");}
+        }
+        return;
+    }
+    match result.pv.get(1) {
+        Some(ponderMove) => {tell!(out, "bestmove {} ponder {}\n",result.bestMove.coordString(),ponderMove.coordString());}
+        None => {tell!(out, "bestmove {}\n",result.bestMove.coordString());}
+    }
+}
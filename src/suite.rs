@@ -0,0 +1,134 @@
+use std::fmt::{Display, Formatter};
+use std::io::Write;
+use std::time::Duration;
+
+use crate::board::BoardState;
+use crate::board::fen::FenError;
+use crate::board::packedmove::PackedMove;
+use crate::engine::Engine;
+use crate::engine::limits::SearchLimits;
+use crate::notation;
+
+/// One position from a test suite, and what the engine should (or shouldn't) play in it.
+pub struct SuitePosition {
+    /// The id operation if there was one, otherwise which line of the file it came from.
+    pub id : String,
+    pub position : BoardState,
+    /// From bm: playing any of these solves it.
+    pub bestMoves : Vec<PackedMove>,
+    /// From am: playing any of these fails it.
+    pub avoidMoves : Vec<PackedMove>
+}
+
+/// Why a suite file couldn't be read, along with the (1-based) line at fault.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SuiteError {
+    BadFen(usize, FenError),
+    /// A bm or am move that couldn't be read, or isn't legal in the position.
+    BadMove(usize, String),
+    /// Neither bm nor am, so there'd be no way of telling whether the engine got it right.
+    NoTarget(usize),
+}
+
+impl Display for SuiteError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        return match self {
+            SuiteError::BadFen(line, err) => write!(f, "Line {}: {}", line, err),
+            SuiteError::BadMove(line, moveStr) => write!(f, "Line {}: move '{}' can't be read or isn't legal", line, moveStr),
+            SuiteError::NoTarget(line) => write!(f, "Line {}: no bm or am to check against", line),
+        };
+    }
+}
+
+impl std::error::Error for SuiteError {}
+
+impl SuitePosition {
+    ///Whether this move passes: one of the best moves if there are any, and none of the moves to avoid.
+    pub fn isSolvedBy(&self, packedMove : PackedMove) -> bool {
+        if !self.bestMoves.is_empty() && !self.bestMoves.contains(&packedMove) {
+            return false;
+        }
+        return !self.avoidMoves.contains(&packedMove);
+    }
+}
+
+///Reads one line of EPD: the board and whose turn it is, then operations like bm C2=5; am h2h9; id "Opening 1";
+///Full FENs are fine too, since the move counters just get skipped. Moves can be in any notation parseAnyMove reads.
+///Gives None for blank lines and comments starting with '#'.
+pub fn parseEpdLine(line : &str, lineNumber : usize) -> Result<Option<SuitePosition>, SuiteError> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return Ok(None);
+    }
+    let mut fields = line.split_whitespace();
+    let board = fields.next().unwrap_or_default();
+    let side = fields.next().unwrap_or_default();
+    let position = BoardState::try_from_fen(&format!("{} {}", board, side)).map_err(|err| SuiteError::BadFen(lineNumber, err))?;
+
+    // Whatever's left after the castling & en passant placeholders (and any move counters) is operations
+    let rest : Vec<&str> = fields.skip_while(|field| *field == "-" || field.chars().all(|cara| cara.is_ascii_digit())).collect();
+    let mut ret = SuitePosition { id : lineNumber.to_string(), position, bestMoves : Vec::new(), avoidMoves : Vec::new() };
+    for operation in rest.join(" ").split(';') {
+        let Some((opcode, operands)) = operation.trim().split_once(char::is_whitespace) else {
+            continue;
+        };
+        let operands = operands.trim();
+        let moves = match opcode {
+            "id" => {
+                ret.id = operands.trim_matches('"').to_string();
+                continue;
+            }
+            "bm" => &mut ret.bestMoves,
+            "am" => &mut ret.avoidMoves,
+            _ => continue // Anything we don't use, like c0 comments or dm
+        };
+        for moveStr in operands.split_whitespace() {
+            let packedMove = notation::parseAnyMove(&ret.position, moveStr)
+                .filter(|packedMove| ret.position.getLegalMoves().contains(packedMove))
+                .ok_or_else(|| SuiteError::BadMove(lineNumber, moveStr.to_string()))?;
+            moves.push(packedMove);
+        }
+    }
+    if ret.bestMoves.is_empty() && ret.avoidMoves.is_empty() {
+        return Err(SuiteError::NoTarget(lineNumber));
+    }
+    return Ok(Some(ret));
+}
+
+///Reads a whole suite, one position per line.
+pub fn readSuite(text : &str) -> Result<Vec<SuitePosition>, SuiteError> {
+    let mut ret = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        if let Some(suitePosition) = parseEpdLine(line, i + 1)? {
+            ret.push(suitePosition);
+        }
+    }
+    return Ok(ret);
+}
+
+///Searches every position in the suite with the given limits, writing out how each one went and then how they went overall.
+///Returns how many were solved.
+pub fn runSuite(engine : &mut Engine, suite : &[SuitePosition], limits : &SearchLimits, out : &mut dyn Write) -> std::io::Result<usize> {
+    let mut solved = 0;
+    let mut nodes = 0;
+    let mut elapsed = Duration::ZERO;
+    for suitePosition in suite {
+        engine.clearHash(); // So that how each position goes doesn't depend on the ones before it
+        let result = engine.searchWithLimits(&suitePosition.position, &[], limits, &mut |_| {});
+        nodes += result.nodes;
+        elapsed += result.elapsed;
+        let played = engine.getNotation().formatMoveWithCoordinates(&suitePosition.position, result.bestMove);
+        if suitePosition.isSolvedBy(result.bestMove) {
+            solved += 1;
+            writeln!(out, "{}: solved with {} ({}, depth {})",suitePosition.id,played,result.score,result.depth)?;
+        } else {
+            let expected : Vec<String> = suitePosition.bestMoves.iter().map(|packedMove| format!("bm {}", packedMove))
+                .chain(suitePosition.avoidMoves.iter().map(|packedMove| format!("am {}", packedMove)))
+                .collect();
+            writeln!(out, "{}: FAILED with {} ({}, depth {}) - wanted {}",suitePosition.id,played,result.score,result.depth,expected.join(", "))?;
+        }
+    }
+    let percent = if suite.is_empty() { 0.0 } else { 100.0 * solved as f32 / suite.len() as f32 };
+    writeln!(out, "Solved {} of {} ({:.1}%), failed {}, in {} nodes and {:.2}s",solved,suite.len(),percent,suite.len() - solved,nodes,elapsed.as_secs_f32())?;
+    return Ok(solved);
+}
@@ -5,7 +5,14 @@ use crate::board;
 use crate::board::packedmove::PackedCoord;
 use crate::engine;
 use crate::board::packedmove::{PackedMove,DEAD_PIECE_PACKEDCOORD};
-use crate::engine::score::{ScoreF32,BLACK_WON,INVALID_POS,RED_WON};
+use crate::engine::score::{ScoreF32,ScoreI32,BLACK_WON,INVALID_POS,RED_WON};
+use crate::board::fen::FenError;
+use crate::board::tile::{Tile,PieceIndex};
+
+/// Shorthand for a move the test knows is well-formed, like "h2e2".
+fn coord(moveStr : &str) -> PackedMove {
+    return moveStr.parse().unwrap();
+}
 
 #[test]
 pub fn FEN_starting_position() { // Tests that basic reading/writing of FENs works
@@ -14,6 +21,38 @@ pub fn FEN_starting_position() { // Tests that basic reading/writing of FENs wor
     assert_eq!(starting_board.writeFEN(),board::STARTING_POSITION_FEN);
 }
 
+#[test]
+pub fn FEN_errors() { // Tests that bad FENs get turned away with the right complaint instead of panicking
+    let cases : &[(&str, FenError)] = &[
+        ("", FenError::Empty),
+        ("rheakaehr/9/1c5c1/p1p1p1p1p/9/P1P1P1P1P/1C5C1/9/RHEAKAEHR w - - 0 1", FenError::WrongRankCount(9)),
+        ("rheakaehr/9/1c5c1/p1p1p1p1p/9/9/P1P1P1P1P/1C5C1/9/RHEAKAEH w - - 0 1", FenError::WrongRankLength(0)),
+        ("rheakaehr/9/1c5c1/p1p1p1p1p/9/9/P1P1P1P1P/1C5C1/9/RHEAKAEHRR w - - 0 1", FenError::WrongRankLength(0)),
+        ("rheakaehr/9/1c5c1/p1p1p1p1p/9/9/P1P1P1P1P/1C5C1/9/RHEAKAEHX w - - 0 1", FenError::UnknownPiece('X')),
+        ("rheakaehr/9/1c5c1/p1p1p1p1p/9/9/P1P1P1P1P/1C5C1/R8/RHEAKAEHR w - - 0 1", FenError::TooManyPieces('R')),
+        ("rheakaehr/9/1c5c1/p1p1p1p1p/9/9/P1P1P1P1P/1C2A2C1/9/RHE1KAEHR w - - 0 1", FenError::ImpossibleSquare('A',(4,2))),
+        ("rheakaehr/9/1c5c1/p1p1p1p1p/9/4E4/P1P1P1P1P/1C5C1/9/RH1AKAEHR w - - 0 1", FenError::ImpossibleSquare('E',(4,4))),
+        ("rhea1aehr/9/1c5c1/p1p1p1p1p/9/9/P1P1P1P1P/1C5C1/9/RHEAKAEHR w - - 0 1", FenError::MissingKing(false)),
+        ("rheakaehr/9/1c5c1/p1p1p1p1p/9/9/P1P1P1P1P/1C5C1/9/RHEAKAEHR", FenError::MissingSideToMove),
+        ("rheakaehr/9/1c5c1/p1p1p1p1p/9/9/P1P1P1P1P/1C5C1/9/RHEAKAEHR x - - 0 1", FenError::BadSideToMove("x".to_string())),
+        ("rheakaehr/9/1c5c1/p1p1p1p1p/9/9/P1P1P1P1P/1C5C1/9/RHEAKAEHR w - - zero 1", FenError::BadMoveCounter("zero".to_string())),
+        ("rheakaehr/9/1c5c1/p1p1p1p1p/9/9/P1P1P1P1P/1C5C1/9/RHEAKAEHR w - - 0 0", FenError::BadMoveCounter("0".to_string())),
+        ("rheakaehr/9/1c5c1/p1p1p1p1p/9/9/P1P1P1P1P/1C5C1/9/RHEAKAEHR w x y 5 9", FenError::BadPlaceholder("x".to_string())),
+        ("rheakaehr/9/1c5c1/p1p1p1p1p/9/9/P1P1P1P1P/1C5C1/9/RHEAKAEHR w - e3 0 1", FenError::BadPlaceholder("e3".to_string())),
+        ("rheakaehr/9/1c5c1/p1p1p1p1p/9/9/P1P1P1P1P/1C5C1/9/RHEAKAEHR w - - 0 1 junk", FenError::TrailingField("junk".to_string())),
+        ("rheakaehr/9/1c5c1/p1p1p1p1p/9/9/P1P1P1P1P/1C5C1/9/RHEAKAEHR w 0 1 2", FenError::TrailingField("2".to_string())),
+    ];
+    for (fen, error) in cases {
+        assert_eq!(board::BoardState::try_from_fen(fen).err().as_ref(),Some(error),"{}",fen);
+    }
+    // Trailing metadata is optional, and the alternate piece letters are fine
+    assert!(board::BoardState::try_from_fen("rnbakabnr/9/1c5c1/p1p1p1p1p/9/9/P1P1P1P1P/1C5C1/9/RNBAKABNR b").is_ok());
+    // So are the castling and en passant placeholders, as long as the counters after them still get read
+    let bare = board::BoardState::try_from_fen("rheakaehr/9/1c5c1/p1p1p1p1p/9/9/P1P1P1P1P/1C5C1/9/RHEAKAEHR b 7 12").unwrap();
+    assert_eq!((bare.halfMoveClock, bare.plyNumber),(7, 23));
+    assert!(bare == board::BoardState::new_from_FEN("rheakaehr/9/1c5c1/p1p1p1p1p/9/9/P1P1P1P1P/1C5C1/9/RHEAKAEHR b - - 7 12"));
+}
+
 #[test]
 pub fn ruleset_starting_position() { // Tests that, at least in the starting position, we output the correct number of moves.
     let starting_board = board::BoardState::new();
@@ -27,11 +66,109 @@ pub fn ruleset_starting_position() { // Tests that, at least in the starting pos
     }
 }
 
+/// Published perft numbers for Xiangqi, as (FEN, [depth 1, depth 2, ...]).
+/// Other than the starting position, these are the usual "tricky" positions full of pins, blocked horses & cannon screens.
+const PERFT_TABLE : &[(&str, &[u64])] = &[
+    (board::STARTING_POSITION_FEN, &[44, 1920, 79666, 3290240]),
+    ("r1ba1a3/4kn3/2n1b4/pNp1p1p1p/4c4/6P2/P1P2R2P/1CcC5/9/2BAKAB2 w - - 0 1", &[38, 1128, 43929, 1339047]),
+    ("5a3/3k5/3aR4/9/5r3/5n3/9/3A1A3/5K3/2BC2B2 w - - 0 1", &[25, 424, 9850, 202884]),
+    ("CRN1k1b2/3ca4/4ba3/9/2nr5/9/9/4B4/4A4/4KA3 w - - 0 1", &[28, 516, 14808, 395483]),
+    ("R1N1k1b2/9/3aba3/9/2nr5/2B6/9/4B4/4A4/4KA3 w - - 0 1", &[21, 364, 7626, 162837]),
+    ("C1nNk4/9/9/9/9/9/n1pp5/B3C4/9/3A1K3 w - - 0 1", &[28, 222, 6241, 64971]),
+];
+
+#[test]
+pub fn ruleset_perft() { // Tests the move generator against the perft table, depth by depth
+    for (fen, counts) in PERFT_TABLE {
+        let board = board::BoardState::new_from_FEN(fen);
+        for (depth, count) in counts.iter().enumerate() {
+            let depth = depth as u32 + 1;
+            let result = board.perft(depth);
+            if result != *count {
+                for (packedMove, subcount) in board.divide(depth) {
+                    println!("{}: {}",packedMove,subcount);
+                }
+                panic!("Wrong perft for {} at depth {}: expected {}, got {}",fen,depth,count,result);
+            }
+        }
+    }
+}
+
 #[test]
 pub fn ruleset_branch() { // Tests that, like, moving pieces around works
     let mut starting_board = board::BoardState::new();
     starting_board = starting_board.branch(PackedMove::new_from_Coords(((0,3),(0,4)))); // Move a pawn!
-    assert_eq!(starting_board.writeFEN(),"rheakaehr/9/1c5c1/p1p1p1p1p/9/P8/2P1P1P1P/1C5C1/9/RHEAKAEHR b - - 0 1");
+    assert_eq!(starting_board.writeFEN(),"rheakaehr/9/1c5c1/p1p1p1p1p/9/P8/2P1P1P1P/1C5C1/9/RHEAKAEHR b - - 1 1");
+}
+
+#[test]
+pub fn FEN_half_move_clock() { // Tests that the plies since the last capture survive FENs and moves, and that running out of them draws
+    use board::rules::{self,GameResult};
+    let fen = "4k4/9/9/9/9/9/c8/9/9/R2K5 w - - 37 50";
+    let mut board = board::BoardState::new_from_FEN(fen);
+    assert_eq!(board.halfMoveClock,37);
+    assert_eq!(board.writeFEN(),fen);
+    let quiet = board.make_move(PackedMove::new_from_coordString("a0a1").unwrap());
+    assert_eq!(board.halfMoveClock,38);
+    board.unmake_move(quiet);
+    let capture = board.make_move(PackedMove::new_from_coordString("a0a3").unwrap());
+    assert_eq!(board.halfMoveClock,0);
+    board.unmake_move(capture);
+    assert_eq!(board.writeFEN(),fen);
+
+    let almost = board::BoardState::new_from_FEN("4k4/9/9/9/9/9/9/9/9/R2K5 w - - 119 80");
+    assert_eq!(rules::adjudicate(&almost, &[], 120),None);
+    let over = almost.branch(PackedMove::new_from_coordString("a0a1").unwrap());
+    assert_eq!(rules::adjudicate(&over, &[], 120),Some((GameResult::Draw,"Draw by the no-capture rule")));
+    assert_eq!(rules::adjudicate(&over, &[], 0),None);
+    assert_eq!(engine::Engine::evalToDepth(&almost, 1),ScoreI32::DRAW); // A whole rook up, but nothing left to take
+}
+
+#[test]
+pub fn ruleset_make_unmake() { // Tests that unmaking a move puts the board back exactly how it was, captures and all
+    for (fen, _) in PERFT_TABLE {
+        let original = board::BoardState::new_from_FEN(fen);
+        let mut board = original.clone();
+        for packedMove in original.getAllMoves() {
+            let undo = board.make_move(packedMove);
+            assert!(board == original.branch(packedMove),"make_move and branch disagree on {} in {}",packedMove,fen);
+            assert_eq!(board.getZobristKey(),board.computeZobristKey(),"incremental key went wrong after {} in {}",packedMove,fen);
+            for reply in board.clone().getAllMoves() { // and one ply deeper, so that captures of captures get a look in
+                let replyUndo = board.make_move(reply);
+                board.unmake_move(replyUndo);
+            }
+            board.unmake_move(undo);
+            assert!(board == original,"unmaking {} didn't restore {}",packedMove,fen);
+            assert_eq!(board.writeFEN(),original.writeFEN());
+        }
+    }
+}
+
+#[test]
+pub fn zobrist_test() { // Tests that the Zobrist key only cares about the pieces & whose move it is
+    let starting_board = board::BoardState::new();
+    assert_eq!(starting_board.getZobristKey(),starting_board.computeZobristKey());
+    assert_ne!(starting_board.getZobristKey(),0);
+
+    // Same position, reached in a different order
+    let horses = [((1,0),(2,2)), ((1,9),(2,7)), ((7,0),(6,2)), ((7,9),(6,7))];
+    let mut inOrder = starting_board.clone();
+    for movePair in horses {
+        inOrder = inOrder.branch(PackedMove::new_from_Coords(movePair));
+    }
+    let mut outOfOrder = starting_board.clone();
+    for movePair in [horses[2], horses[3], horses[0], horses[1]] {
+        outOfOrder = outOfOrder.branch(PackedMove::new_from_Coords(movePair));
+    }
+    assert_eq!(inOrder.getZobristKey(),outOfOrder.getZobristKey());
+    assert_eq!(inOrder.getZobristKey(),inOrder.computeZobristKey());
+    assert_ne!(inOrder.getZobristKey(),starting_board.getZobristKey());
+
+    // The move number doesn't matter, but whose move it is does
+    let later = board::BoardState::new_from_FEN("rheakaehr/9/1c5c1/p1p1p1p1p/9/9/P1P1P1P1P/1C5C1/9/RHEAKAEHR w - - 0 30");
+    assert_eq!(later.getZobristKey(),starting_board.getZobristKey());
+    let blackToMove = board::BoardState::new_from_FEN("rheakaehr/9/1c5c1/p1p1p1p1p/9/9/P1P1P1P1P/1C5C1/9/RHEAKAEHR b - - 0 1");
+    assert_ne!(blackToMove.getZobristKey(),starting_board.getZobristKey());
 }
 
 #[test]
@@ -40,12 +177,78 @@ pub fn ruleset_shy_general() { // Tests that the shy general rule works
     assert_eq!(board.countMoves(),3);
 }
 
+#[test]
+pub fn ruleset_legal_moves() { // Tests that moves which expose the king get filtered out
+    assert_eq!(board::BoardState::new().countLegalMoves(),44);
+
+    let pinned = board::BoardState::new_from_FEN("4k4/9/9/9/9/4H4/9/9/9/4K4 w - - 0 1"); // Horse is all that keeps the generals apart
+    assert_eq!(pinned.countMoves(),11);
+    assert_eq!(pinned.countLegalMoves(),3);
+
+    let checked = board::BoardState::new_from_FEN("3k5/9/9/9/4r4/9/9/R8/9/4K4 w - - 0 1"); // Has to block or step aside, but not into the other general
+    assert!(checked.isInCheck());
+    let mut evasions : Vec<String> = checked.getLegalMoves().iter().map(|packedMove| packedMove.to_string()).collect();
+    let mut expected = vec![
+        PackedMove::new_from_Coords(((0,2),(4,2))).to_string(), // block
+        PackedMove::new_from_Coords(((4,0),(5,0))).to_string() // step aside
+    ];
+    evasions.sort();
+    expected.sort();
+    assert_eq!(evasions,expected);
+}
+
+#[test]
+pub fn ruleset_check_detection() {
+    let in_check = [
+        "3k5/9/9/4c4/9/9/4P4/9/9/4K4 w - - 0 1", // cannon through a screen
+        "3k5/9/9/9/9/9/9/5h3/9/4K4 w - - 0 1", // horse
+        "3k5/9/9/9/9/9/9/9/4p4/4K4 w - - 0 1", // pawn
+        "3k5/9/9/9/9/9/9/9/9/3pK4 w - - 0 1", // pawn, sideways
+        "4k4/4P4/9/9/9/9/9/9/9/3K5 b - - 0 1", // pawn, other direction
+        "4k4/9/9/9/9/9/9/9/9/4K4 b - - 0 1", // flying general
+    ];
+    for fen in in_check {
+        assert!(board::BoardState::new_from_FEN(fen).isInCheck(),"{} should be check",fen);
+    }
+    let not_in_check = [
+        "3k5/9/9/9/4c4/9/9/9/9/4K4 w - - 0 1", // cannon with no screen
+        "3k5/9/9/9/9/9/9/5h3/5C3/4K4 w - - 0 1", // horse with a blocked leg
+        "3k5/9/9/9/9/9/9/9/4K4/4p4 w - - 0 1", // pawns can't attack backwards
+    ];
+    for fen in not_in_check {
+        assert!(!board::BoardState::new_from_FEN(fen).isInCheck(),"{} should not be check",fen);
+    }
+}
+
+#[test]
+pub fn ruleset_elephant_eye() { // Tests that elephants can't jump over a piece sitting on the midpoint of their move
+    let elephant_moves = |fen : &str, elephant : (usize,usize)| -> Vec<String> {
+        let board = board::BoardState::new_from_FEN(fen);
+        let start = PackedCoord::new_from_Coord(elephant);
+        let mut ret : Vec<String> = board.getAllMoves().iter()
+            .filter(|packedMove| packedMove.start() == start)
+            .map(|packedMove| packedMove.to_string())
+            .collect();
+        ret.sort();
+        return ret;
+    };
+    // Red elephant with one eye blocked by a friendly cannon and the other by an enemy horse
+    assert!(elephant_moves("3k5/9/9/9/9/9/9/9/1h1C5/2E1K4 w - - 0 1",(2,0)).is_empty());
+    assert_eq!(elephant_moves("3k5/9/9/9/9/9/9/9/3C5/2E1K4 w - - 0 1",(2,0)),vec![PackedMove::new_from_Coords(((2,0),(0,2))).to_string()]);
+    // Black elephant in the middle of its side, blocked on two of its four diagonals
+    let mut expected = vec![
+        PackedMove::new_from_Coords(((4,7),(2,5))).to_string(),
+        PackedMove::new_from_Coords(((4,7),(6,9))).to_string()
+    ];
+    expected.sort();
+    assert_eq!(elephant_moves("4k4/3c5/4e4/5P3/9/9/9/9/9/3K5 b - - 0 1",(4,7)),expected);
+}
+
 #[test]
 pub fn ruleset_cannon() {
     let board = board::BoardState::new_from_FEN("1rbakabCr/9/4c2c1/p1p1p1p1p/9/9/P1P1P1P1P/9/9/RNBAKABNR w - - 0 1");
     let val = engine::Engine::evalToDepth(&board, 2);
-    assert_ne!(val,RED_WON);
-    assert_ne!(val,BLACK_WON);
+    assert_eq!(val.mateDistance(),None);
 }
 
 #[test]
@@ -60,6 +263,64 @@ pub fn score_test() {
     assert!(RED_WON > ScoreF32::new(5f32));
 }
 
+#[test]
+pub fn score_mate_distance() { // Tests that quicker mates are better for whoever's mating, and that mates survive the trip up & down the tree
+    let red_mates_in_1 = ScoreF32::redMatesIn(1);
+    let red_mates_in_3 = ScoreF32::redMatesIn(3);
+    let black_mates_in_2 = ScoreF32::blackMatesIn(2);
+    let black_mates_in_4 = ScoreF32::blackMatesIn(4);
+    assert_eq!(ScoreF32::redMatesIn(0),RED_WON);
+    assert_eq!(ScoreF32::blackMatesIn(0),BLACK_WON);
+    assert!(RED_WON > red_mates_in_1);
+    assert!(red_mates_in_1 > red_mates_in_3);
+    assert!(red_mates_in_3 > ScoreF32::new(f32::INFINITY));
+    assert!(black_mates_in_4 < ScoreF32::new(f32::NEG_INFINITY));
+    assert!(black_mates_in_2 < black_mates_in_4);
+    assert!(BLACK_WON < black_mates_in_2);
+    assert_eq!(red_mates_in_3.redMateDistance(),Some(3));
+    assert_eq!(red_mates_in_3.blackMateDistance(),None);
+    assert_eq!(black_mates_in_4.mateDistance(),Some(4));
+    assert_eq!(ScoreF32::new(1.5).mateDistance(),None);
+    assert_eq!(INVALID_POS.mateDistance(),None);
+    assert_eq!(RED_WON.toParent(),red_mates_in_1);
+    assert_eq!(red_mates_in_1.toChild(),RED_WON);
+    assert_eq!(black_mates_in_2.toParent().toChild(),black_mates_in_2);
+    assert_eq!(INVALID_POS.toParent(),INVALID_POS);
+    assert_eq!(format!("{}",red_mates_in_3),"Red mates in 2");
+    assert_eq!(format!("{}",black_mates_in_4),"Black mates in 2");
+    assert_eq!(red_mates_in_3.mateInMoves(true),Some(2));
+    assert_eq!(black_mates_in_4.mateInMoves(true),Some(-2));
+}
+
+#[test]
+pub fn score_integer() { // Tests that the integer scores order the same way as the float ones, and convert back & forth without losing anything
+    let scores = [
+        ScoreF32::blackMatesIn(0), ScoreF32::blackMatesIn(2), ScoreF32::blackMatesIn(7), ScoreF32::new(-12.5),
+        ScoreF32::new(0.0), ScoreF32::new(0.25), ScoreF32::new(9.0), ScoreF32::redMatesIn(5), ScoreF32::redMatesIn(1), ScoreF32::redMatesIn(0)
+    ];
+    let converted : Vec<ScoreI32> = scores.iter().map(|score| ScoreI32::new_from_f32(*score).expect("Only INVALID_POS has no integer score")).collect();
+    for i in 1..scores.len() {
+        assert!(scores[i - 1] < scores[i]);
+        assert!(converted[i - 1] < converted[i]);
+    }
+    for (score, integer) in scores.iter().zip(converted.iter()) {
+        assert_eq!(integer.toF32(),*score);
+        assert_eq!(integer.mateDistance(),score.mateDistance());
+        assert_eq!(format!("{}",integer),format!("{}",score));
+    }
+    assert_eq!(ScoreI32::new_from_f32(INVALID_POS),None);
+    assert_eq!(ScoreI32::new_from_f32(RED_WON),Some(ScoreI32::RED_WON));
+    assert_eq!(ScoreI32::new_from_f32(BLACK_WON),Some(ScoreI32::BLACK_WON));
+    assert_eq!(ScoreI32::default(),ScoreI32::DRAW);
+    assert_eq!(-ScoreI32::redMatesIn(3),ScoreI32::blackMatesIn(3));
+    assert_eq!(ScoreI32::redMatesIn(3).toParent(),ScoreI32::redMatesIn(4));
+    assert_eq!(ScoreI32::redMatesIn(3).mateInMoves(false),Some(-1));
+    // Even absurd evaluations never get mistaken for mates
+    assert_eq!(ScoreI32::new_from_eval(1_000_000).mateDistance(),None);
+    assert!(ScoreI32::new_from_eval(-1_000_000) > ScoreI32::blackMatesIn(999));
+    assert!(ScoreI32::RED_WON < ScoreI32::INFINITY && -ScoreI32::INFINITY < ScoreI32::BLACK_WON);
+}
+
 #[test]
 pub fn iterator_asserts() { // Tests that iterating tiles & pieces both work :)
     let starting_board = board::BoardState::new();
@@ -70,7 +331,46 @@ pub fn iterator_asserts() { // Tests that iterating tiles & pieces both work :)
 #[test]
 pub fn packedmove_test() {
     let packer : PackedMove = PackedMove::new_from_Coords(((0,0),(0,1)));
-    assert_eq!(format!("{}",packer),"a1b1");
+    assert_eq!(format!("{}",packer),"a0a1");
+    assert_eq!(format!("{}",PackedMove::new()),"????");
+}
+
+#[test]
+pub fn packedmove_roundtrip() { // Tests that every move between any two squares prints as coordinates and parses back to itself
+    use crate::board::packedmove::MoveParseError;
+    for startX in 0..9 {
+        for startY in 0..10 {
+            for endX in 0..9 {
+                for endY in 0..10 {
+                    let packedMove = PackedMove::new_from_Coords(((startX,startY),(endX,endY)));
+                    let written = packedMove.to_string();
+                    assert_eq!(written,format!("{}{}{}{}",(b'a' + startX as u8) as char,startY,(b'a' + endX as u8) as char,endY));
+                    assert_eq!(written.parse::<PackedMove>(),Ok(packedMove));
+                    assert_eq!(written.to_uppercase().parse::<PackedMove>(),Ok(packedMove));
+                }
+            }
+        }
+    }
+    assert_eq!("h2-e2".parse::<PackedMove>(),Ok(PackedMove::new_from_Coords(((7,2),(4,2)))));
+    assert_eq!("j2e2".parse::<PackedMove>(),Err(MoveParseError::BadSquare(String::from("j2"))));
+    assert_eq!("h2e".parse::<PackedMove>(),Err(MoveParseError::WrongLength(String::from("h2e"))));
+    assert_eq!("????".parse::<PackedMove>(),Err(MoveParseError::BadSquare(String::from("??"))));
+}
+
+#[test]
+pub fn tile_asserts() { // Tests that pieces really do fit in a byte, and survive the trip to & from FEN characters
+    assert_eq!(std::mem::size_of::<Tile>(),1);
+    assert_eq!(std::mem::size_of::<board::TileGrid>(),90);
+    for cara in "pPaAeEhHcCrRkK".chars() {
+        let pieceIndex = PieceIndex::new_from_char(cara).unwrap();
+        assert_eq!(pieceIndex.asChar(),cara);
+        assert_eq!(pieceIndex.isRed(),cara.is_ascii_uppercase());
+    }
+    assert_eq!(PieceIndex::new_from_char('N'),PieceIndex::new_from_char('H'));
+    assert_eq!(PieceIndex::new_from_char('b'),PieceIndex::new_from_char('e'));
+    assert_eq!(PieceIndex::new_from_char('x'),None);
+    assert!(!Tile::new().hasPiece());
+    assert_eq!(Tile::new().pieceIndex.asChar(),'\0');
 }
 
 #[test]
@@ -80,6 +380,88 @@ pub fn packedcoord_asserts() {
     assert_eq!(packer.data,255u8);
 }
 
+#[test]
+pub fn rules_repetition() { // Tests that repetitions are spotted, and that whoever's been checking or chasing the whole time gets the blame
+    use board::rules::{self,Repetition,GameResult};
+    let play = |fen : &str, moves : &[&str]| -> (board::BoardState, Vec<board::UndoRecord>) {
+        let mut state = board::BoardState::new_from_FEN(fen);
+        let mut history = Vec::new();
+        for moveStr in moves {
+            let packedMove = PackedMove::new_from_coordString(moveStr).unwrap();
+            assert!(state.getLegalMoves().contains(&packedMove),"{} isn't legal in {}",moveStr,state.writeFEN());
+            history.push(state.make_move(packedMove));
+        }
+        return (state, history);
+    };
+
+    let shuffle = ["b0c2","b9c7","c2b0","c7b9"];
+    let (once, onceHistory) = play(board::STARTING_POSITION_FEN, &shuffle);
+    assert_eq!(rules::findRepetition(&once, &onceHistory, 1),Some(Repetition::Draw));
+    assert_eq!(rules::findRepetition(&once, &onceHistory, 2),None);
+    assert_eq!(rules::adjudicate(&once, &onceHistory, rules::DEFAULT_NO_CAPTURE_PLIES),None);
+    let (thrice, thriceHistory) = play(board::STARTING_POSITION_FEN, &[shuffle, shuffle].concat());
+    assert_eq!(rules::adjudicate(&thrice, &thriceHistory, rules::DEFAULT_NO_CAPTURE_PLIES),Some((GameResult::Draw,"Draw by repetition")));
+    let (half, halfHistory) = play(board::STARTING_POSITION_FEN, &shuffle[..2]);
+    assert_eq!(rules::findRepetition(&half, &halfHistory, 1),None);
+    assert_eq!(rules::findRepetition(&once, &[], 1),None); // Nothing to go on without the moves that got here
+
+    let (checks, checksHistory) = play("4k4/9/9/9/9/R8/9/9/9/3K5 w - - 0 1", &["a4a9","e9e8","a9a8","e8e9","a8a9","e9e8"]);
+    assert_eq!(rules::findRepetition(&checks, &checksHistory, 1),Some(Repetition::PerpetualCheck { byRed : true }));
+    // Black can walk straight back into the repetition, which the search should like without calling it mate
+    let (checked, checkedHistory) = play("4k4/9/9/9/9/R8/9/9/9/3K5 w - - 0 1", &["a4a9","e9e8","a9a8","e8e9","a8a9"]);
+    let result = engine::Engine::new().searchWithLimits(&checked, &checkedHistory, &engine::limits::SearchLimits::new_from_depth(3), &mut |_| {});
+    assert_eq!(result.bestMove,PackedMove::new_from_coordString("e9e8").unwrap());
+    assert_eq!(result.score.mateDistance(),None);
+    assert!(result.score.centipawns(false) > 10000,"{}",result.score);
+
+    let (chases, chasesHistory) = play("4k4/9/9/9/2c6/R8/9/9/9/3K5 w - - 0 1", &["a4a5","c5c6","a5a6","c6c5","a6a5","c5c6"]);
+    assert_eq!(rules::findRepetition(&chases, &chasesHistory, 1),Some(Repetition::PerpetualChase { byRed : true }));
+    assert_eq!(Repetition::PerpetualChase { byRed : true }.loserIsRed(),Some(true));
+    // The horse going back and forth lets one rook or the other at a horse every time, without attacking anything itself
+    let (discovered, discoveredHistory) = play("4k4/9/h1h6/9/9/9/9/H8/9/R1RK5 w - - 0 1", &["a2c3","e9e8","c3a2","e8e9"]);
+    assert_eq!(rules::findRepetition(&discovered, &discoveredHistory, 1),Some(Repetition::PerpetualChase { byRed : true }));
+
+    // A game keeps the history for whichever position it's at
+    let mut game = crate::game::Game::new();
+    for moveStr in shuffle {
+        game.playMove(PackedMove::new_from_coordString(moveStr).unwrap());
+    }
+    assert_eq!(game.history(),&onceHistory[..]);
+    assert_eq!(rules::findRepetition(game.position(), game.history(), 1),Some(Repetition::Draw));
+    game.undo();
+    assert_eq!(game.history().len(),3);
+    assert_eq!(rules::findRepetition(game.position(), game.history(), 1),None);
+}
+
+#[test]
+pub fn game_undo_redo() { // Tests stepping back and forth through a game, and that playing something new forgets the old line
+    use crate::game::Game;
+    let horse = |moveStr : &str| PackedMove::new_from_coordString(moveStr).unwrap();
+    let mut game = Game::new();
+    for moveStr in ["b0c2","b9c7","c2b0"] {
+        game.playMove(horse(moveStr));
+    }
+    assert_eq!(game.currentPly(),3);
+    assert_eq!(game.undo(),Some(horse("c2b0")));
+    assert_eq!(game.undo(),Some(horse("b9c7")));
+    assert_eq!(game.moves(),&[horse("b0c2")]);
+    assert_eq!(game.allMoves().len(),3);
+    assert_eq!(game.redo(),Some(horse("b9c7")));
+    assert!(game.position() == &board::BoardState::new().branch(horse("b0c2")).branch(horse("b9c7")));
+
+    assert!(game.goto(0));
+    assert!(game.position() == &board::BoardState::new());
+    assert_eq!(game.undo(),None);
+    assert!(!game.goto(4));
+    game.playMove(horse("b0c2")); // Same as what's already there, so the rest of the line survives
+    assert_eq!(game.allMoves().len(),3);
+    game.playMove(horse("h9g7"));
+    assert_eq!(game.allMoves(),&[horse("b0c2"),horse("h9g7")]);
+    assert_eq!(game.redo(),None);
+    assert_eq!(game.startFen(),board::STARTING_POSITION_FEN);
+    assert!(Game::new_from_fen("not a fen").is_err());
+}
+
 #[test]
 pub fn engine_starting_position() {
     let starting_board = board::BoardState::new();
@@ -87,7 +469,7 @@ pub fn engine_starting_position() {
     let displayResult = format!("{}",score);
     //we can't make too many assertions about what the engine thinks about the starting position,
     //but there are a few obvious things we can do here
-    assert_ne!(score,INVALID_POS);
+    assert!(score.mateDistance().is_none());
     assert_ne!(displayResult,"inf");
     assert_ne!(displayResult,"-inf");
 }
@@ -96,27 +478,453 @@ pub fn engine_starting_position() {
 pub fn engine_mated_position() {
     let mate_one = board::BoardState::new_from_FEN("R3k4/R8/9/9/9/9/9/9/9/5K3 b - - 0 22"); // backrank mate
     let mate_one_score = engine::Engine::evalToDepth(&mate_one, 4);
-    assert_eq!(mate_one_score,RED_WON);
+    assert_eq!(mate_one_score,ScoreI32::RED_WON);
 }
 
 #[test]
 pub fn engine_mate_in_one() {
     let mate_one = board::BoardState::new_from_FEN("2eakaer1/4h4/4H1h2/p1P1p1p1p/9/8P/P5P2/E3C1H1C/6r2/3AKAE1R r - - 0 22"); // smothered mate
     let mate_one_score = engine::Engine::evalToDepth(&mate_one, 4);
-    assert_eq!(mate_one_score,RED_WON);
+    assert_eq!(mate_one_score,ScoreI32::redMatesIn(1));
+    assert_eq!(format!("{}",mate_one_score),"Red mates in 1");
 }
 
 #[test]
 pub fn engine_mate_in_two() {
     let mate_two = board::BoardState::new_from_FEN("4P4/4ak3/1r4N2/6p1p/4p4/6P2/Pc3r2P/4CR3/4A4/1RBK1ABN1 w - - 0 1"); // Mate in two (with pins)
     let mate_two_score = engine::Engine::evalToDepth(&mate_two, 5);
-    assert_eq!(mate_two_score,RED_WON);
+    assert_eq!(mate_two_score,ScoreI32::redMatesIn(3));
 }
 
 #[test]
 pub fn engine_mate_in_three() {
     let mate_three = board::BoardState::new_from_FEN("2C1k4/4a4/4ca3/8R/p8/2P6/P5P1P/4C4/1R2A4/1NBK1ABN1 w - - 0 1");
     let mate_three_score = engine::Engine::evalToDepth(&mate_three, 6);
-    assert_eq!(mate_three_score,RED_WON);
+    // There's a mate in three here, but Ri9 mates right away too (the advisor can't block without exposing the king to the e-file cannon)
+    assert_eq!(mate_three_score,ScoreI32::redMatesIn(1));
+}
+
+
+#[test]
+pub fn engine_transposition_table() { // Tests the table itself, then that searching with a warm table doesn't change the answer
+    use engine::transposition::{self,TranspositionTable,TableEntry,Bound};
+    let mut table = TranspositionTable::new(1);
+    assert_eq!(table.sizeInMegabytes(),1);
+    let position = board::BoardState::new();
+    let key = position.getZobristKey();
+    assert!(table.probe(key).is_none());
+
+    let bestMove = position.getLegalMoves()[0];
+    table.store(TableEntry { key, score : ScoreI32::new(150), bestMove, depth : 4, bound : Bound::Exact });
+    let entry = table.probe(key).expect("Entry should have been stored");
+    assert_eq!(entry.bestMove,bestMove);
+    assert_eq!(entry.bound,Bound::Exact);
+    // A shallower search of the same position shouldn't clobber a deeper one
+    table.store(TableEntry { key, score : ScoreI32::new(-150), bestMove, depth : 2, bound : Bound::Upper });
+    assert_eq!(table.probe(key).unwrap().depth,4);
+    table.clear();
+    assert!(table.probe(key).is_none());
+    // Sizes from the command line, REPL and GUIs have to be ones we can actually allocate
+    assert_eq!(TranspositionTable::new(0).sizeInMegabytes(),1);
+    assert_eq!(transposition::parseHashSize("4096"),Some(4096));
+    for bad in ["0", "4097", "18446744073709551615", "-1", "lots"] {
+        assert_eq!(transposition::parseHashSize(bad),None);
+    }
+
+    let mate_two = board::BoardState::new_from_FEN("4P4/4ak3/1r4N2/6p1p/4p4/6P2/Pc3r2P/4CR3/4A4/1RBK1ABN1 w - - 0 1");
+    let mut engine = engine::Engine::new();
+    engine.setHashSize(1);
+    assert_eq!(engine.search(&mate_two, &[], 5),ScoreI32::redMatesIn(3));
+    assert_eq!(engine.search(&mate_two, &[], 5),ScoreI32::redMatesIn(3));
 }
 
+#[test]
+pub fn engine_quiescence() { // Tests that the engine sees the recapture coming, even when it's past the end of the search
+    let poisoned_pawn = board::BoardState::new_from_FEN("r3k4/9/9/9/p8/9/9/9/R8/3K5 w - - 0 1");
+    let grabbed = poisoned_pawn.branch(PackedMove::new_from_packed(PackedCoord::new_from_usize(0,1),PackedCoord::new_from_usize(0,5)));
+    let score = engine::Engine::evalToDepth(&poisoned_pawn, 1);
+    assert!(score < grabbed.getValue(),"Engine thinks the pawn is free ({} vs {})",score,grabbed.getValue());
+}
+
+#[test]
+pub fn engine_search_limits() { // Tests that 'go' arguments turn into the right limits, and that those limits get respected
+    use std::time::{Duration,Instant};
+    use engine::limits::SearchLimits;
+    let limits = SearchLimits::parseGoArguments(&["wtime","60000","btime","30000","winc","1000","depth","12"]).unwrap();
+    assert_eq!(limits.depth,Some(12));
+    assert_eq!(limits.redTime,Some(Duration::from_millis(60000)));
+    assert_eq!(limits.blackIncrement,Duration::ZERO);
+    assert!(SearchLimits::parseGoArguments(&["depth"]).is_err());
+    assert!(SearchLimits::parseGoArguments(&["depth","deep"]).is_err());
+    assert!(SearchLimits::parseGoArguments(&["sideways","3"]).is_err());
+    assert!(SearchLimits::parseGoArguments(&["movestogo","99999999999"]).is_err());
+    // UCCI clocks are ours & theirs, and in seconds unless told otherwise; big ones mustn't wrap round
+    let (ucci, infinite) = SearchLimits::parseUcciGoArguments(&["time","5000000000","increment","2","opptime","30"], false, false).unwrap();
+    assert!(!infinite);
+    assert_eq!(ucci.blackTime,Some(Duration::from_secs(5000000000)));
+    assert_eq!(ucci.blackIncrement,Duration::from_secs(2));
+    assert_eq!(ucci.redTime,Some(Duration::from_secs(30)));
+    assert_eq!(SearchLimits::parseUcciGoArguments(&["time","1500"], true, true).unwrap().0.redTime,Some(Duration::from_millis(1500)));
+    assert!(SearchLimits::parseUcciGoArguments(&["time","-5"], true, false).is_err());
+    assert!(SearchLimits::parseUcciGoArguments(&["time"], true, false).is_err());
+    let restricted = SearchLimits::parseGoArguments(&["searchmoves","a3a4","i3i4","mate","2","depth","5"]).unwrap();
+    assert_eq!(restricted.searchMoves,vec![PackedMove::new_from_coordString("a3a4").unwrap(), PackedMove::new_from_coordString("i3i4").unwrap()]);
+    assert_eq!(restricted.depth,Some(3)); // Mate in 2 only needs 3 plies
+    let played = engine::Engine::new().searchWithLimits(&board::BoardState::new(), &[], &restricted, &mut |_| {}).bestMove;
+    assert!(restricted.searchMoves.contains(&played),"{} wasn't one of the moves to search",played);
+
+    let start = Instant::now();
+    let deadline = limits.deadline(true, start).unwrap();
+    assert!(deadline.soft <= deadline.hard);
+    assert!(deadline.hard < start + Duration::from_millis(30000)); // Never anywhere near our whole clock
+    assert!(limits.deadline(false, start).unwrap().hard < deadline.hard); // Black has less time and no increment
+    assert!(SearchLimits::new_from_depth(3).deadline(true, start).is_none());
+
+    let starting_board = board::BoardState::new();
+    let legalMoves = starting_board.getLegalMoves();
+    let mut engine = engine::Engine::new();
+    let now = Instant::now();
+    let result = engine.searchWithLimits(&starting_board, &[], &SearchLimits::new_from_movetime(Duration::from_millis(300)), &mut |_| {});
+    assert!(now.elapsed() < Duration::from_secs(2),"Search ran long: {:?}",now.elapsed());
+    assert!(legalMoves.contains(&result.bestMove));
+
+    let nodeLimits = SearchLimits { nodes : Some(20000), ..Default::default() };
+    let result = engine.searchWithLimits(&starting_board, &[], &nodeLimits, &mut |_| {});
+    assert!(legalMoves.contains(&result.bestMove));
+    assert!(result.nodes < 40000);
+
+    // Even a search that's told to stop before it even starts still has a move to give
+    engine.stopHandle().store(true, std::sync::atomic::Ordering::Relaxed);
+    let result = engine.searchWithLimits(&starting_board, &[], &SearchLimits::default(), &mut |_| {});
+    assert_eq!(result.depth,1);
+    assert!(legalMoves.contains(&result.bestMove));
+}
+
+#[test]
+pub fn packedmove_coordstring() { // Tests reading & writing moves the way UCCI does it
+    let cannonMove = PackedMove::new_from_coordString("h2e2").unwrap();
+    assert_eq!(cannonMove,PackedMove::new_from_packed(PackedCoord::new_from_usize(7,2),PackedCoord::new_from_usize(4,2)));
+    assert_eq!(cannonMove.coordString(),"h2e2");
+    assert_eq!(PackedMove::new_from_coordString("H2E2"),Some(cannonMove));
+    assert_eq!(PackedMove::new_from_coordString("h2-e2"),Some(cannonMove));
+    assert_eq!(PackedMove::new_from_coordString("H2-e2"),Some(cannonMove));
+    assert!(board::BoardState::new().getLegalMoves().contains(&cannonMove));
+    for bad in ["", "h2e", "h2e22", "j2e2", "h2e-", "h-e2", "h2=e2", "h2--e2", "-h2e2"] {
+        assert_eq!(PackedMove::new_from_coordString(bad),None,"{}",bad);
+    }
+}
+
+#[test]
+pub fn notation_wxf() { // Tests writing & reading WXF moves, tandem pieces included, and that every legal move makes the round trip
+    use crate::notation::wxf;
+    let start = board::BoardState::new();
+    let cases = [("h2e2","C2=5"),("h0g2","H2+3"),("a0a1","R9+1"),("d0e1","A6+5"),("c0e2","E7+5"),("e0e1","K5+1"),("g3g4","P3+1"),("b2b9","C8+7")];
+    for (moveStr, expected) in cases {
+        assert_eq!(wxf::formatMove(&start, coord(moveStr)),expected);
+        assert_eq!(wxf::parseMove(&start, expected),Some(coord(moveStr)));
+    }
+    let afterCannon = start.branch(coord("h2e2"));
+    assert_eq!(wxf::formatMove(&afterCannon, coord("b9c7")),"H2+3"); // Black counts files from its own right
+    assert_eq!(wxf::formatMove(&afterCannon, coord("h7e7")),"C8=5");
+    assert_eq!(wxf::parseMove(&afterCannon, "h8+7"),Some(coord("h9g7")));
+    assert_eq!(wxf::parseMove(&afterCannon, "c8.5"),Some(coord("h7e7")));
+    assert_eq!(wxf::parseMove(&afterCannon, "N8+7"),Some(coord("h9g7")));
+    assert_eq!(wxf::parseMove(&afterCannon, "C2=5"),Some(coord("b7e7")));
+    assert_eq!(wxf::parseMove(&start, "H2=3"),None);
+    assert_eq!(wxf::parseMove(&start, "P5+2"),None);
+
+    let rooks = board::BoardState::new_from_FEN("5k3/9/9/9/9/4R4/9/9/9/3KR4 w - - 0 1");
+    assert_eq!(wxf::formatMove(&rooks, coord("e4d4")),"R+=6");
+    assert_eq!(wxf::formatMove(&rooks, coord("e0f0")),"R-=4");
+    assert_eq!(wxf::parseMove(&rooks, "+R=6"),Some(coord("e4d4")));
+    let pawns = board::BoardState::new_from_FEN("5k3/9/4P4/4P4/4P4/9/9/9/9/3K5 w - - 0 1");
+    assert_eq!(wxf::formatMove(&pawns, coord("e6f6")),"2P=4");
+    assert_eq!(wxf::formatMove(&pawns, coord("e7e8")),"1P+1");
+    let twoFiles = board::BoardState::new_from_FEN("5k3/9/9/2P1P4/2P1P4/9/9/9/9/3K5 w - - 0 1");
+    assert_eq!(wxf::formatMove(&twoFiles, coord("e6f6")),"P+5=4");
+    assert_eq!(wxf::formatMove(&twoFiles, coord("c5b5")),"P-7=8");
+
+    for position in [start, afterCannon, rooks, pawns, twoFiles] {
+        for packedMove in position.getLegalMoves() {
+            let written = wxf::formatMove(&position, packedMove);
+            assert_eq!(wxf::parseMove(&position, &written),Some(packedMove),"{} didn't read back in {}",written,position.writeFEN());
+        }
+    }
+}
+
+#[test]
+pub fn notation_iccs_chinese() { // Tests ICCS & Chinese moves both ways, red and black numbering, and that every legal move makes the round trip
+    use crate::notation::{chinese, iccs, parseAnyMove, Notation};
+    let start = board::BoardState::new();
+    assert_eq!(iccs::formatMove(coord("h2e2")),"H2-E2");
+    assert_eq!(iccs::parseMove("H2-E2"),Some(coord("h2e2")));
+    assert_eq!(iccs::formatMove(PackedMove::new()),"????");
+
+    assert_eq!(chinese::formatMove(&start, coord("h2e2")),"炮二平五");
+    assert_eq!(chinese::formatMove(&start, coord("h0g2")),"马二进三");
+    assert_eq!(chinese::parseMove(&start, "炮二平五"),Some(coord("h2e2")));
+    assert_eq!(chinese::parseMove(&start, "砲二平五"),Some(coord("h2e2"))); // Traditional
+    assert_eq!(chinese::parseMove(&start, "炮2平5"),Some(coord("h2e2")));
+    assert_eq!(chinese::parseMove(&start, "炮二进五"),None);
+    let afterCannon = start.branch(coord("h2e2"));
+    assert_eq!(chinese::formatMove(&afterCannon, coord("h9g7")),"马８进７"); // Black uses full-width digits, from its own right
+    assert_eq!(chinese::parseMove(&afterCannon, "馬8進7"),Some(coord("h9g7")));
+    assert_eq!(chinese::formatMove(&afterCannon, coord("a6a5")),"卒１进１");
+
+    let rooks = board::BoardState::new_from_FEN("5k3/9/9/9/9/4R4/9/9/9/3KR4 w - - 0 1");
+    assert_eq!(chinese::formatMove(&rooks, coord("e4d4")),"前车平六");
+    assert_eq!(chinese::formatMove(&rooks, coord("e0f0")),"后车平四");
+    assert_eq!(chinese::parseMove(&rooks, "後車平四"),Some(coord("e0f0")));
+    let pawns = board::BoardState::new_from_FEN("5k3/9/4P4/4P4/4P4/9/9/9/9/3K5 w - - 0 1");
+    assert_eq!(chinese::formatMove(&pawns, coord("e6f6")),"中兵平四");
+
+    assert_eq!(parseAnyMove(&start, "h2e2"),Some(coord("h2e2")));
+    assert_eq!(parseAnyMove(&start, "C2=5"),Some(coord("h2e2")));
+    assert_eq!(parseAnyMove(&start, "炮二平五"),Some(coord("h2e2")));
+    assert_eq!(Notation::new_from_name("Chinese"),Some(Notation::Chinese));
+    assert_eq!(Notation::Iccs.formatMoveWithCoordinates(&start, coord("h2e2")),"H2-E2");
+    assert_eq!(Notation::Wxf.formatMoveWithCoordinates(&start, coord("h2e2")),"C2=5 (h2e2)");
+
+    for position in [start, afterCannon, rooks, pawns] {
+        for packedMove in position.getLegalMoves() {
+            let written = chinese::formatMove(&position, packedMove);
+            assert_eq!(chinese::parseMove(&position, &written),Some(packedMove),"{} didn't read back in {}",written,position.writeFEN());
+            assert_eq!(iccs::parseMove(&iccs::formatMove(packedMove)),Some(packedMove));
+        }
+    }
+}
+
+#[test]
+pub fn pgn_roundtrip() { // Tests that games survive being written out & read back in, in each notation, comments and all
+    use crate::pgn::{self,PgnGame};
+    use crate::notation::Notation;
+    use board::rules::GameResult;
+    let mut game = crate::game::Game::new_from_fen("rheakaehr/9/1c5c1/p1p1p1p1p/9/9/P1P1P1P1P/1C5C1/9/RHEAKAEHR b - - 0 3").unwrap();
+    for moveStr in ["h9g7","h2e2","i9h9","h0g2","h9h8"] {
+        game.playMove(PackedMove::new_from_coordString(moveStr).unwrap());
+    }
+    let mut pgnGame = PgnGame::new(game, Some(GameResult::Draw));
+    pgnGame.tags.push((String::from("Annotator"), String::from("Someone \"quoted\"")));
+    pgnGame.comments = vec![(0, String::from("Black to move")), (2, String::from("Central cannon")), (5, String::from("Rook out"))];
+    for notation in [Notation::Wxf, Notation::Iccs, Notation::Chinese] {
+        let written = pgn::writePgn(&pgnGame, notation);
+        assert!(written.contains("[Variant \"Xiangqi\"]"));
+        assert!(written.contains("[Result \"1/2-1/2\"]"));
+        let read = pgn::readPgn(&written).unwrap();
+        assert_eq!(read.tags,pgnGame.tags);
+        assert_eq!(read.game.startFen(),pgnGame.game.startFen());
+        assert_eq!(read.game.moves(),pgnGame.game.moves());
+        assert!(read.game.position() == pgnGame.game.position());
+        assert_eq!(read.result,Some(GameResult::Draw));
+        assert_eq!(read.comments,pgnGame.comments);
+    }
+    let written = pgn::writePgn(&pgnGame, Notation::Wxf);
+    assert!(written.replace('\n', " ").contains("{Black to move} 3... H8+7 4. C2=5 {Central cannon} 4... R9=8 5. H2+3 R8+1 {Rook out} 1/2-1/2"),"{}",written);
+}
+
+#[test]
+pub fn pgn_read() { // Tests reading past the things other programs put in PGN files, and complaining about broken ones
+    use crate::pgn::{self,PgnError};
+    use board::rules::GameResult;
+    let text = "[Event \"Test\"]\n[Format \"ICCS\"]\n\n1. H2-E2 $1 H9-G7 (1... H7-E7 2. H0-G2) 2.H0-G2!? {A comment (with parentheses)}\n; Another\n2... I9-H9\n3. C2=5?? 0-1\n";
+    assert_eq!(pgn::readPgn(text).err(),Some(PgnError::BadMove(4, String::from("C2=5"))));
+    let read = pgn::readPgn(&text.replace("3. C2=5??", "3. R1+1")).unwrap();
+    assert_eq!(read.tags,vec![(String::from("Event"), String::from("Test"))]);
+    assert_eq!(read.game.moves().iter().map(|packedMove| packedMove.to_string()).collect::<Vec<String>>(),vec!["h2e2","h9g7","h0g2","i9h9","i0i1"]);
+    assert_eq!(read.comments,vec![(3, String::from("A comment (with parentheses)")), (3, String::from("Another"))]);
+    assert_eq!(read.result,Some(GameResult::BlackWins));
+
+    assert_eq!(pgn::readPgn("1. h2e2 {Unfinished").err(),Some(PgnError::UnterminatedComment));
+    assert_eq!(pgn::readPgn("1. h2e2 (1. b2e2").err(),Some(PgnError::UnbalancedVariation));
+    assert_eq!(pgn::readPgn("[FEN \"9/9 w\"]\n").err().map(|err| matches!(err, PgnError::BadFen(_))),Some(true));
+    assert_eq!(pgn::readPgn("[Event Test]\n").err(),Some(PgnError::BadTag(String::from("[Event Test]"))));
+}
+
+/// Writes a little XQF file the way XQStudio would: the opening position, a main line of h2e2 h9g7 with b2e2 as an alternative to h2e2, and some comments.
+fn writeTestXqf(version : u8, keyHeader : [u8;16]) -> Vec<u8> {
+    use crate::xqf::XqfKeys;
+    let mut bytes = vec![0u8;1024];
+    bytes[..16].copy_from_slice(&keyHeader);
+    bytes[0] = b'X';
+    bytes[1] = b'Q';
+    bytes[2] = version;
+    let keys = XqfKeys::new(&bytes);
+    let pieces : [u8;32] = [0,10,20,30,40,50,60,70,80,12,72,3,23,43,63,83, 9,19,29,39,49,59,69,79,89,17,77,6,26,46,66,86];
+    for i in 0..32 {
+        let index = if version >= 12 { (i + keys.pieces as usize + 1) & 31 } else { i };
+        bytes[16 + i] = pieces[index].wrapping_add(keys.pieces);
+    }
+    bytes[51] = 3; // Drawn
+    bytes[304] = 2;
+    bytes[305..307].copy_from_slice(b"Lu");
+
+    let (next, variation) = if version <= 10 { (0xF0, 0x0F) } else { (0x80, 0x40) };
+    // (start, end, flags, comment) for the root, h2e2, h9g7 and b2e2
+    let records : [(u8,u8,u8,&str);4] = [(0,0,next,"Start"), (72,42,next | variation,"Central cannon"), (79,67,0,""), (12,42,0,"")];
+    let mut plain = Vec::new();
+    for (start, end, flags, comment) in records {
+        let hasComment = version <= 10 || !comment.is_empty();
+        plain.push(start.wrapping_add(0x18).wrapping_add(keys.moveStart));
+        plain.push(end.wrapping_add(0x18).wrapping_add(keys.moveEnd));
+        plain.push(flags | if hasComment && version > 10 { 0x20 } else { 0 });
+        plain.push(0);
+        if hasComment {
+            plain.extend_from_slice(&(comment.len() as i32 + keys.commentLength).to_le_bytes());
+            plain.extend_from_slice(comment.as_bytes());
+        }
+    }
+    for (i, byte) in plain.into_iter().enumerate() {
+        bytes.push(byte.wrapping_add(keys.stream[(1024 + i) % 32]));
+    }
+    return bytes;
+}
+
+#[test]
+pub fn xqf_read() { // Tests reading XQF files, both plain and encrypted, move tree and all, and turning them into PGN
+    use crate::xqf::{self,XqfError};
+    use board::rules::GameResult;
+    let plain = writeTestXqf(10, [0;16]);
+    let encrypted = writeTestXqf(18, [0,0,0,0xA5, 1,2,3,4, 0x5C,0x11,0x22,0x33, 0x7E,0x3D,0x91,0xC4]);
+    for bytes in [plain, encrypted.clone()] {
+        let xqfGame = xqf::readXqf(&bytes).unwrap();
+        assert!(xqfGame.startPosition == board::BoardState::new());
+        assert_eq!(xqfGame.result,Some(GameResult::Draw));
+        assert_eq!(xqfGame.tags,vec![(String::from("Red"), String::from("Lu"))]);
+        assert_eq!(xqfGame.nodes.len(),4);
+        assert_eq!(xqfGame.nodes[0].comment,"Start");
+        assert_eq!(xqfGame.nodes[0].children,vec![1,3]);
+        assert_eq!(xqfGame.nodes[3].packedMove,coord("b2e2"));
+        let game = xqfGame.toGame();
+        assert_eq!(game.moves(),&[coord("h2e2"), coord("h9g7")]);
+        let pgnGame = xqfGame.toPgn();
+        assert_eq!(pgnGame.comments,vec![(0, String::from("Start")), (1, String::from("Central cannon"))]);
+        assert!(pgnGame.tags.contains(&(String::from("Red"), String::from("Lu"))));
+    }
+    let mut gbk = encrypted.clone();
+    gbk[320..325].copy_from_slice(&[4, 0xC2,0xC0,0xC7,0xD5]); // Black's name, in GBK
+    assert!(xqf::readXqf(&gbk).unwrap().tags.contains(&(String::from("Black"), String::from("吕钦"))));
+    assert_eq!(xqf::readXqf(&encrypted[..1030]).err(),Some(XqfError::Truncated));
+    assert_eq!(xqf::readXqf(b"PK").err(),Some(XqfError::BadSignature));
+    let mut corrupted = encrypted.clone();
+    corrupted[1024 + 4 + 4 + 5] ^= 0x01; // h2e2's starting square, just past the root's record and comment
+    assert!(matches!(xqf::readXqf(&corrupted).err(),Some(XqfError::BadMove(0, _))));
+}
+
+#[test]
+pub fn xqf_fixture() { // Tests reading an encrypted XQF 1.8 file put together byte by byte from the format's description, not by writeTestXqf, so a mistake in the keys can't cancel itself out
+    use crate::xqf;
+    use board::rules::GameResult;
+    let mut bytes = vec![0u8;1024];
+    let header : [(usize, &[u8]);6] = [
+        (0, &[0x58,0x51,0x12,0x5B,0x30,0x31,0x32,0x33,0x10,0x82,0x04,0x41,0xC5,0x2F,0x63,0xB1,0xFF,0x13,0xBD,0xC7,0xD1,0xDB,0xE5,0xEF,0xF9,0x03,0x0D,0xC9,
+            0xE7,0xC0,0xD4,0xE8,0xFC,0x10,0xC6,0xD0,0xDA,0xE4,0xEE,0xF8,0x02,0x0C,0x16,0xCE,0x0A,0xC3,0xD7,0xEB]),
+        (50, &[0x01,0x02]),
+        (80, &[0x0C,0xD6,0xD0,0xC5,0xDA,0xB6,0xD4,0xC6,0xC1,0xB7,0xE7,0xC2,0xED]),
+        (208, &[0x0E,0xC8,0xAB,0xB9,0xFA,0xCF,0xF3,0xC6,0xE5,0xB8,0xF6,0xC8,0xCB,0xC8,0xFC]),
+        (272, &[0x07,0x31,0x39,0x39,0x39,0x2E,0x31,0x30]),
+        (304, &[0x06,0xD0,0xED,0xD2,0xF8,0xB4,0xA8, 0,0,0,0,0,0,0,0,0, 0x04,0xC2,0xC0,0xC7,0xD5])
+    ];
+    for (offset, field) in header {
+        bytes[offset..offset + field.len()].copy_from_slice(field);
+    }
+    bytes.extend_from_slice(&[0xE0,0xB5,0xE3,0x01,0x36,0x4E,0x47,0x50,0x0B,0xDC,0xF8,0xFE,0x0F,0xC8,0xD7,0x20,0x2E,0xFA,0xA0,0x40,0x73,0x55,0x47,0x00,
+        0x1D,0x00,0x03,0x3E,0xFA,0xE4,0x86,0x51,0xF3,0xD0,0x43,0x01]);
+
+    let xqfGame = xqf::readXqf(&bytes).unwrap();
+    assert!(xqfGame.startPosition == board::BoardState::try_from_fen("rnbakabnr/9/1c5c1/p1p1p1p1p/9/9/P1P1P1P1P/1C2C4/9/RNBAKABNR b").unwrap());
+    assert_eq!(xqfGame.result,Some(GameResult::BlackWins));
+    let tag = |name : &str, value : &str| (name.to_string(), value.to_string());
+    assert_eq!(xqfGame.tags,vec![tag("Event", "全国象棋个人赛"), tag("Date", "1999.10"), tag("Red", "许银川"), tag("Black", "吕钦"), tag("Title", "中炮对屏风马")]);
+    assert_eq!(xqfGame.nodes[0].comment,"黑方先走");
+    assert_eq!(xqfGame.nodes[1].comment,"跳马");
+    assert_eq!(xqfGame.toGame().moves(),&[coord("h9g7"), coord("e2e6"), coord("b9c7")]);
+}
+
+#[test]
+pub fn suite_epd() { // Tests reading EPD suites, and that running one counts up what the engine gets right
+    use crate::suite::{self,SuiteError};
+    use engine::limits::SearchLimits;
+    let text = "# Rook endings\n\n3k5/9/9/9/9/r8/9/9/9/R3K4 w - - bm a0d0 a0a4; id \"Pin the king\";\n3k5/9/9/9/9/r8/9/9/9/R3K4 w - - 0 1 am R9+1 a0b0\n";
+    let positions = suite::readSuite(text).unwrap();
+    assert_eq!(positions.len(),2);
+    assert_eq!(positions[0].id,"Pin the king");
+    assert_eq!(positions[0].bestMoves,vec![coord("a0d0"), coord("a0a4")]);
+    assert_eq!(positions[1].id,"4");
+    assert_eq!(positions[1].avoidMoves,vec![coord("a0a1"), coord("a0b0")]);
+    assert!(positions[1].isSolvedBy(coord("a0d0")));
+    assert!(!positions[1].isSolvedBy(coord("a0b0")));
+
+    let mut engine = engine::Engine::new();
+    let limits = SearchLimits { depth : Some(3), ..Default::default() };
+    let mut report = Vec::new();
+    assert_eq!(suite::runSuite(&mut engine, &positions, &limits, &mut report).unwrap(),2);
+    let report = String::from_utf8(report).unwrap();
+    assert!(report.starts_with("Pin the king: solved with"),"{}",report);
+    assert!(report.ends_with("\n") && report.lines().last().unwrap().starts_with("Solved 2 of 2 (100.0%), failed 0"),"{}",report);
+
+    assert_eq!(suite::readSuite("3k5/9/9/9/9/r8/9/9/9/R3K4 w - - id \"Nothing\";").err(),Some(SuiteError::NoTarget(1)));
+    assert_eq!(suite::readSuite("\n3k5/9/9/9/9/r8/9/9/9/R3K4 w - - bm a0a5;").err(),Some(SuiteError::BadMove(2, String::from("a0a5"))));
+    assert!(matches!(suite::readSuite("3k5/9 w - - bm a0a4;").err(),Some(SuiteError::BadFen(1, _))));
+}
+
+#[test]
+pub fn xboard_usermove_after_engine_move() { // Tests that a reply sent as soon as the engine moves gets checked against the board with that move played
+    use std::sync::{Arc,Mutex};
+    let output = Arc::new(Mutex::new(Vec::<u8>::new()));
+    let mut session = crate::xboard::XboardSession::new_with_output(engine::Engine::new(), output.clone());
+    for cmdstr in ["new","sd 2","usermove h2e2","usermove i0i1","force","ping 1"] {
+        assert!(session.handleCommand(cmdstr));
+    }
+    let text = String::from_utf8(output.lock().unwrap().clone()).unwrap();
+    assert!(text.lines().any(|line| line.starts_with("move ")),"{}",text);
+    assert!(!text.contains("Illegal move"),"{}",text);
+    assert!(text.ends_with("pong 1\n"),"{}",text);
+}
+
+#[test]
+pub fn protocol_no_move() { // Tests that a go we can't make sense of and a position without any moves get the same null answer
+    use std::sync::{Arc,Mutex};
+    use crate::protocol::{Protocol,ProtocolSession};
+    for (protocol, nullAnswer) in [(Protocol::Uci, "bestmove (none)"), (Protocol::Ucci, "nobestmove")] {
+        let output = Arc::new(Mutex::new(Vec::<u8>::new()));
+        let mut session = ProtocolSession::new_with_output(protocol, engine::Engine::new(), output.clone());
+        for cmdstr in ["position startpos","go depth x","position fen R3k4/1R7/9/9/9/9/9/9/9/3K5 b","go depth 1","quit"] {
+            session.handleCommand(cmdstr);
+        }
+        let text = String::from_utf8(output.lock().unwrap().clone()).unwrap();
+        assert_eq!(text.lines().filter(|line| *line == nullAnswer).count(),2,"{}",text);
+    }
+}
+
+#[test]
+pub fn engine_banned_moves() { // Tests that banned moves never come back as the best move, even the only good one
+    use engine::limits::SearchLimits;
+    let mate_one = board::BoardState::new_from_FEN("2eakaer1/4h4/4H1h2/p1P1p1p1p/9/8P/P5P2/E3C1H1C/6r2/3AKAE1R r - - 0 22");
+    let mut engine = engine::Engine::new();
+    let mateMove = engine.searchWithLimits(&mate_one, &[], &SearchLimits::new_from_depth(2), &mut |_| {}).bestMove;
+    let banned = SearchLimits { depth : Some(2), bannedMoves : vec![mateMove], ..Default::default() };
+    let result = engine.searchWithLimits(&mate_one, &[], &banned, &mut |_| {});
+    assert_ne!(result.bestMove,mateMove);
+    assert!(mate_one.getLegalMoves().contains(&result.bestMove));
+    assert_eq!(result.pv.first(),Some(&result.bestMove));
+}
+
+#[test]
+pub fn engine_multipv() { // Tests that asking for several lines gets that many different first moves, best line first
+    use engine::limits::SearchLimits;
+    let starting_board = board::BoardState::new();
+    let mut engine = engine::Engine::new();
+    let mut lines : Vec<engine::SearchResult> = Vec::new();
+    let limits = SearchLimits { depth : Some(3), multiPv : 4, ..Default::default() };
+    let result = engine.searchWithLimits(&starting_board, &[], &limits, &mut |line| {
+        if line.depth == 3 {
+            lines.push(line.clone());
+        }
+    });
+    assert_eq!(lines.iter().map(|line| line.rank).collect::<Vec<usize>>(),vec![1,2,3,4]);
+    assert_eq!(lines[0].bestMove,result.bestMove);
+    for (i, line) in lines.iter().enumerate() {
+        assert!(lines[..i].iter().all(|better| better.bestMove != line.bestMove));
+        assert!(lines[..i].iter().all(|better| better.score >= line.score)); // Red to move, so higher is better
+    }
+}
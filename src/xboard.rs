@@ -0,0 +1,324 @@
+use std::io::{stdin, stdout};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use crate::board::{BoardState, UndoRecord};
+use crate::board::rules;
+use crate::board::packedmove::PackedMove;
+use crate::engine::{Engine, SearchResult};
+use crate::engine::limits::{SearchLimits, MAX_SEARCH_DEPTH};
+use crate::{tell, Output};
+
+/// The clock settings from 'level', 'st' and 'sd'. CECP calls Red "white" and Black "black".
+#[derive(Clone, Copy, Debug, Default)]
+struct TimeControl {
+    movesPerSession : u32, // 0 means the whole game is one session
+    increment : Duration,
+    moveTime : Option<Duration>, // From 'st', which overrides everything else
+    depth : Option<i32>,
+    ourTime : Option<Duration>, // From 'time' and 'otim', which the GUI sends before each of our moves
+    theirTime : Option<Duration>
+}
+
+/// Talks CECP (the XBoard/WinBoard protocol) with the GUI.
+/// Unlike UCCI and UCI, the engine keeps track of the game itself here, and decides for itself when it's its turn.
+pub struct XboardSession {
+    boardPosition : BoardState,
+    history : Vec<UndoRecord>, // The moves that got us to boardPosition, for undo and for spotting repetitions
+    engine : Option<Engine>, // None while the search thread has it
+    searchThread : Option<JoinHandle<(Engine, Option<SearchResult>)>>,
+    stopSignal : Arc<AtomicBool>,
+    discardSearch : Arc<AtomicBool>, // Set when the search we're stopping shouldn't get to play its move
+    engineIsRed : Option<bool>, // None in force mode, where we just keep track of the moves we're told
+    timeControl : TimeControl,
+    post : bool, // Whether to show our thinking
+    out : Output
+}
+
+impl XboardSession {
+    pub fn new(engine : Engine) -> Self {
+        return Self::new_with_output(engine, Arc::new(Mutex::new(stdout())));
+    }
+
+    pub fn new_with_output(engine : Engine, out : Output) -> Self {
+        return Self {
+            boardPosition : BoardState::new(),
+            history : Vec::new(),
+            stopSignal : engine.stopHandle(),
+            engine : Some(engine),
+            searchThread : None,
+            discardSearch : Arc::new(AtomicBool::new(false)),
+            engineIsRed : Some(false),
+            timeControl : TimeControl::default(),
+            post : false,
+            out
+        };
+    }
+
+    /// Reads commands from stdin until told to quit (or stdin runs dry).
+    pub fn run(&mut self) {
+        tell!(self.out, "\n"); // The GUI expects a newline back for the 'xboard' that got us here
+        loop {
+            let mut cmdstr = String::new();
+            if stdin().read_line(&mut cmdstr).unwrap_or(0) == 0 {
+                self.abandonSearch();
+                return;
+            }
+            if !self.handleCommand(cmdstr.trim()) {
+                return;
+            }
+        }
+    }
+
+    /// Returns false once it's time to quit.
+    pub fn handleCommand(&mut self, cmdstr : &str) -> bool {
+        let words : Vec<&str> = cmdstr.split_ascii_whitespace().collect();
+        if words.is_empty() {
+            return true;
+        }
+        if words[0] == "?" { // Move now
+            self.stopSignal.store(true, Ordering::Relaxed);
+            return true;
+        }
+        if words[0] == "ping" { // Only answered once everything before it is done, which includes any search we're running
+            self.finishSearch();
+            tell!(self.out, "pong {}\n",words.get(1).unwrap_or(&""));
+            return true;
+        }
+        // Anything else, we first catch up on whatever move we might have just played. The GUI may have seen it already,
+        // so anything that goes by the position has to wait for it; the rest (new, force, undo and so on) stop the search and wait anyway
+        if matches!(words[0], "usermove" | "go" | "playother") {
+            self.finishSearch();
+        } else {
+            self.collectSearch();
+        }
+        match words[0] {
+            "protover" => {
+                tell!(self.out, "feature myname=\"Liyu {}\" variants=\"xiangqi\" setboard=1 usermove=1 ping=1 playother=1 colors=0 sigint=0 sigterm=0 analyze=0 done=1\n",env!("CARGO_PKG_VERSION"));
+            }
+            "new" => {
+                self.abandonSearch();
+                self.boardPosition = BoardState::new();
+                self.history.clear();
+                self.engineIsRed = Some(false);
+                self.timeControl.depth = None;
+                self.engine.as_mut().expect("Engine should be back from searching").clearHash();
+            }
+            "variant" => {
+                if words.get(1) != Some(&"xiangqi") {
+                    tell!(self.out, "Error (unsupported variant): {}\n",words.get(1).unwrap_or(&""));
+                }
+            }
+            "setboard" => {
+                self.abandonSearch();
+                match BoardState::try_from_fen(&words[1..].join(" ")) {
+                    Ok(newPosition) => {
+                        self.boardPosition = newPosition;
+                        self.history.clear();
+                    }
+                    Err(err) => {tell!(self.out, "tellusererror Illegal position: {}\n",err);}
+                }
+            }
+            "usermove" => {
+                let moveStr = words.get(1).unwrap_or(&"");
+                match PackedMove::new_from_coordString(moveStr) {
+                    Some(packedMove) if self.boardPosition.getLegalMoves().contains(&packedMove) => {
+                        self.playMove(packedMove);
+                        self.thinkIfOurTurn();
+                    }
+                    _ => {tell!(self.out, "Illegal move: {}\n",moveStr);}
+                }
+            }
+            "go" => {
+                self.engineIsRed = Some(self.boardPosition.isRedTurn);
+                self.thinkIfOurTurn();
+            }
+            "playother" => {
+                self.engineIsRed = Some(!self.boardPosition.isRedTurn);
+            }
+            "force" | "result" => {
+                self.abandonSearch();
+                self.engineIsRed = None;
+            }
+            "undo" => {
+                self.abandonSearch();
+                self.takeBack(1);
+            }
+            "remove" => {
+                self.abandonSearch();
+                self.takeBack(2);
+            }
+            "level" => {
+                if let Err(err) = self.setLevel(&words[1..]) {
+                    tell!(self.out, "Error (bad level): {}\n",err);
+                }
+            }
+            "st" => match words.get(1).and_then(|value| value.parse::<f32>().ok()) {
+                Some(seconds) if seconds >= 0.0 => self.timeControl.moveTime = Some(Duration::from_secs_f32(seconds)),
+                _ => {tell!(self.out, "Error (bad time): {}\n",cmdstr);}
+            },
+            "sd" => match words.get(1).and_then(|value| value.parse::<i32>().ok()) {
+                Some(depth) => self.timeControl.depth = Some(depth.clamp(1, MAX_SEARCH_DEPTH)),
+                None => {tell!(self.out, "Error (bad depth): {}\n",cmdstr);}
+            },
+            "time" | "otim" => match words.get(1).and_then(|value| value.parse::<u64>().ok()) {
+                Some(centiseconds) => {
+                    let time = Some(Duration::from_millis(centiseconds * 10));
+                    if words[0] == "time" {
+                        self.timeControl.ourTime = time;
+                    } else {
+                        self.timeControl.theirTime = time;
+                    }
+                }
+                None => {tell!(self.out, "Error (bad time): {}\n",cmdstr);}
+            },
+            "post" => self.post = true,
+            "nopost" => self.post = false,
+            "quit" => {
+                self.abandonSearch();
+                return false;
+            }
+            // Things we're told but have no use for
+            "xboard" | "accepted" | "rejected" | "random" | "easy" | "hard" | "computer" | "name" | "rating" | "ics" | "draw" => {}
+            _ => {tell!(self.out, "Error (unknown command): {}\n",words[0]);}
+        }
+        return true;
+    }
+
+    /// level MPS BASE INC, where BASE is minutes (or minutes:seconds) and INC is seconds.
+    fn setLevel(&mut self, words : &[&str]) -> Result<(),String> {
+        let [movesPerSession, _base, increment] = words else {
+            return Err(words.join(" "));
+        };
+        self.timeControl.movesPerSession = movesPerSession.parse::<u32>().map_err(|_| movesPerSession.to_string())?;
+        let increment = increment.parse::<f32>().map_err(|_| increment.to_string())?;
+        self.timeControl.increment = Duration::from_secs_f32(increment.max(0.0));
+        self.timeControl.moveTime = None; // 'level' and 'st' replace each other
+        // The base time itself doesn't matter, since 'time' tells us how much we have left before every move
+        return Ok(());
+    }
+
+    fn playMove(&mut self, packedMove : PackedMove) {
+        let undo = self.boardPosition.make_move(packedMove);
+        self.history.push(undo);
+    }
+
+    fn takeBack(&mut self, count : usize) {
+        for _ in 0..count {
+            let Some(undo) = self.history.pop() else {
+                return;
+            };
+            self.boardPosition.unmake_move(undo);
+        }
+    }
+
+    fn searchLimits(&self) -> SearchLimits {
+        let timeControl = &self.timeControl;
+        let engineIsRed = self.boardPosition.isRedTurn;
+        let mut limits = SearchLimits {
+            depth : timeControl.depth,
+            moveTime : timeControl.moveTime,
+            ..Default::default()
+        };
+        if limits.moveTime.is_none() && timeControl.ourTime.is_some() {
+            let (ourTime, theirTime) = if engineIsRed { (&mut limits.redTime, &mut limits.blackTime) } else { (&mut limits.blackTime, &mut limits.redTime) };
+            *ourTime = timeControl.ourTime;
+            *theirTime = timeControl.theirTime;
+            limits.redIncrement = timeControl.increment;
+            limits.blackIncrement = timeControl.increment;
+            if timeControl.movesPerSession > 0 {
+                let ourMovesPlayed = (self.boardPosition.plyNumber / 2) as u32;
+                limits.movesToGo = Some(timeControl.movesPerSession - ourMovesPlayed % timeControl.movesPerSession);
+            }
+        }
+        if limits.depth.is_none() && limits.moveTime.is_none() && limits.redTime.is_none() && limits.blackTime.is_none() {
+            limits.moveTime = Some(Duration::from_secs(5)); // The GUI never told us anything, so don't take forever
+        }
+        return limits;
+    }
+
+    /// Starts searching if it's the engine's move, or announces the result if the game's over.
+    fn thinkIfOurTurn(&mut self) {
+        let noCapturePlies = self.engine.as_ref().expect("Engine should be back from searching").getNoCaptureLimit();
+        if announceResult(&self.out, &self.boardPosition, &self.history, noCapturePlies) || self.engineIsRed != Some(self.boardPosition.isRedTurn) {
+            return;
+        }
+        let limits = self.searchLimits();
+        let mut engine = self.engine.take().expect("Engine should be back from searching");
+        let startState = self.boardPosition.clone();
+        let mut history = self.history.clone();
+        let discardSearch = self.discardSearch.clone();
+        let post = self.post;
+        let out = self.out.clone();
+        discardSearch.store(false, Ordering::Relaxed);
+        self.stopSignal.store(false, Ordering::Relaxed);
+        self.searchThread = Some(thread::spawn(move || {
+            let result = engine.searchWithLimits(&startState, &history, &limits, &mut |result| {
+                if post {
+                    printThinking(&out, result, startState.isRedTurn);
+                }
+            });
+            if discardSearch.load(Ordering::Relaxed) || result.bestMove == PackedMove::new() {
+                return (engine, None);
+            }
+            tell!(out, "move {}\n",result.bestMove.coordString());
+            let mut afterMove = startState.clone();
+            history.push(afterMove.make_move(result.bestMove));
+            announceResult(&out, &afterMove, &history, noCapturePlies);
+            return (engine, Some(result));
+        }));
+    }
+
+    /// If the search is done, plays the move it came up with onto our board too. Doesn't wait for it otherwise.
+    fn collectSearch(&mut self) {
+        if self.searchThread.as_ref().is_some_and(|searchThread| searchThread.is_finished()) {
+            self.finishSearch();
+        }
+    }
+
+    /// Waits for any search to finish (it'll print its own move), then plays that move onto our board.
+    fn finishSearch(&mut self) {
+        let Some(searchThread) = self.searchThread.take() else {
+            return;
+        };
+        let (engine, result) = searchThread.join().expect("Search thread panicked");
+        self.engine = Some(engine);
+        if let Some(result) = result {
+            self.playMove(result.bestMove);
+        }
+    }
+
+    /// Stops any search without playing its move.
+    fn abandonSearch(&mut self) {
+        if self.searchThread.is_none() {
+            return;
+        }
+        self.discardSearch.store(true, Ordering::Relaxed);
+        self.stopSignal.store(true, Ordering::Relaxed);
+        self.finishSearch();
+        self.stopSignal.store(false, Ordering::Relaxed);
+    }
+}
+
+/// Tells the GUI if the game's over, be it by having no moves left, by repetition or by the no-capture rule.
+/// Returns whether the game is over.
+fn announceResult(out : &Output, boardPosition : &BoardState, history : &[UndoRecord], noCapturePlies : u16) -> bool {
+    let Some((result, reason)) = rules::adjudicate(boardPosition, history, noCapturePlies) else {
+        return false;
+    };
+    tell!(out, "{} {{{}}}\n",result,reason);
+    return true;
+}
+
+/// ply score time nodes pv, with score in centipawns for the side to move and time in centiseconds.
+fn printThinking(out : &Output, result : &SearchResult, isRedTurn : bool) {
+    let score = match result.score.mateInMoves(isRedTurn) { // XBoard's convention for "mate in N"
+        Some(moves) if moves > 0 => 100000 + moves,
+        Some(moves) => -100000 + moves,
+        None => result.score.centipawns(isRedTurn)
+    };
+    let pv : Vec<String> = result.pv.iter().map(|packedMove| packedMove.coordString()).collect();
+    tell!(out, "{} {} {} {} {}\n",result.depth,score,result.elapsed.as_millis() / 10,result.nodes,pv.join(" "));
+}
@@ -0,0 +1,324 @@
+use std::fmt::{Display, Formatter};
+
+use crate::board::BoardState;
+use crate::board::fen::FenError;
+use crate::board::packedmove::{PackedCoord, PackedMove};
+use crate::board::rules::GameResult;
+use crate::game::Game;
+use crate::pgn::PgnGame;
+
+/// Everything before the moves. The moves start right after, with the root of the move tree.
+const HEADER_SIZE : usize = 1024;
+
+/// Which piece each of the 32 position bytes is for: Red's first, then Black's.
+const PIECE_ORDER : &[u8;16] = b"RHEAKAEHRCCPPPPP";
+
+/// XQF 1.1 and up mix this into the keys for everything after the header.
+const COPYRIGHT : &[u8;32] = b"[(C) Copyright Mr. Dong Shiwei.]";
+
+/// Where the header keeps its text, as (PGN tag, offset, field size).
+const TEXT_FIELDS : [(&str, usize, usize);7] = [
+    ("Event", 208, 64), ("Date", 272, 16), ("Site", 288, 16), ("Red", 304, 16), ("Black", 320, 16),
+    ("Title", 80, 64), ("Annotator", 464, 16)
+];
+
+/// One move in an XQF file's move tree, along with any comment on it.
+pub struct XqfNode {
+    /// The empty move for the root, which only exists to hold the comment on the starting position.
+    pub packedMove : PackedMove,
+    /// Decoded from GBK, like the header's text.
+    pub comment : String,
+    pub parent : Option<usize>,
+    /// Indexes into XqfGame::nodes. The first child is the main line, and any others are variations on it.
+    pub children : Vec<usize>
+}
+
+/// A game read from an XQF file, the binary format XQStudio and a lot of old databases use. Variations are kept,
+/// as a tree, though only the main line makes it into a Game or PGN.
+pub struct XqfGame {
+    /// The header's text, as PGN tags, decoded from GBK.
+    pub tags : Vec<(String,String)>,
+    pub result : Option<GameResult>,
+    pub startPosition : BoardState,
+    /// The move tree, parents always before their children. nodes[0] is the root.
+    pub nodes : Vec<XqfNode>
+}
+
+/// Why readXqf couldn't make a game out of a file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum XqfError {
+    /// Didn't start with "XQ".
+    BadSignature,
+    /// The file ended partway through the header or a move.
+    Truncated,
+    /// The pieces in the header don't make a valid position.
+    BadPosition(FenError),
+    /// A move (given by how many moves came before it) that's off the board, or isn't legal where it was played.
+    BadMove(usize, String),
+}
+
+impl Display for XqfError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        return match self {
+            XqfError::BadSignature => write!(f, "Invalid XQF: not an XQF file"),
+            XqfError::Truncated => write!(f, "Invalid XQF: file ends too soon"),
+            XqfError::BadPosition(err) => write!(f, "Invalid XQF: bad starting position ({})", err),
+            XqfError::BadMove(ply, moveStr) => write!(f, "Invalid XQF: move {} ({}) isn't legal", ply + 1, moveStr),
+        };
+    }
+}
+
+impl std::error::Error for XqfError {}
+
+/// The keys for undoing the encryption on everything past the header. All zeroes for files from before 1.1, which weren't encrypted.
+pub(crate) struct XqfKeys {
+    pub(crate) pieces : u8,
+    pub(crate) moveStart : u8,
+    pub(crate) moveEnd : u8,
+    pub(crate) commentLength : i32,
+    pub(crate) stream : [u8;32]
+}
+
+impl XqfKeys {
+    pub(crate) fn new(header : &[u8]) -> Self {
+        let version = header[2];
+        if version <= 10 {
+            return Self { pieces : 0, moveStart : 0, moveEnd : 0, commentLength : 0, stream : [0;32] };
+        }
+        let square54Plus221 = |x : u8| x.wrapping_mul(x).wrapping_mul(54).wrapping_add(221);
+        let pieces = square54Plus221(header[13]).wrapping_mul(header[13]);
+        let moveStart = square54Plus221(header[14]).wrapping_mul(pieces);
+        let moveEnd = square54Plus221(header[15]).wrapping_mul(moveStart);
+        let commentLength = ((header[12] as i32 * 256 + header[13] as i32) % 32000) + 767;
+        let mask = header[3];
+        let keyBytes = [
+            (header[12] & mask) | header[8],
+            (header[13] & mask) | header[9],
+            (header[14] & mask) | header[10],
+            (header[15] & mask) | header[11]
+        ];
+        let mut stream = [0;32];
+        for i in 0..32 {
+            stream[i] = COPYRIGHT[i] & keyBytes[i % 4];
+        }
+        return Self { pieces, moveStart, moveEnd, commentLength, stream };
+    }
+}
+
+/// Reads through the moves, decrypting as it goes.
+struct XqfReader<'a> {
+    bytes : &'a [u8],
+    pos : usize,
+    keys : &'a XqfKeys
+}
+
+impl XqfReader<'_> {
+    fn read(&mut self, len : usize) -> Result<Vec<u8>, XqfError> {
+        let end = self.pos.checked_add(len).filter(|&end| end <= self.bytes.len()).ok_or(XqfError::Truncated)?;
+        let ret = (self.pos..end).map(|i| self.bytes[i].wrapping_sub(self.keys.stream[i % 32])).collect();
+        self.pos = end;
+        return Ok(ret);
+    }
+}
+
+/// One move's record, before we know whether it means anything.
+struct XqfRecord {
+    start : u8,
+    end : u8,
+    hasNext : bool,
+    hasVariation : bool,
+    comment : String
+}
+
+fn readRecord(reader : &mut XqfReader, version : u8) -> Result<XqfRecord, XqfError> {
+    let record = reader.read(4)?;
+    let tag = record[2];
+    // Before 1.1 every move had a comment length, even if it was 0, and the flags were a bit looser
+    let (hasNext, hasVariation, hasComment) = if version <= 10 {
+        (tag & 0xF0 != 0, tag & 0x0F != 0, true)
+    } else {
+        (tag & 0x80 != 0, tag & 0x40 != 0, tag & 0x20 != 0)
+    };
+    let mut comment = String::new();
+    if hasComment {
+        let lengthBytes = reader.read(4)?;
+        let length = i32::from_le_bytes([lengthBytes[0], lengthBytes[1], lengthBytes[2], lengthBytes[3]]) - reader.keys.commentLength;
+        if length > 0 {
+            comment = decodeText(&reader.read(length as usize)?);
+        }
+    }
+    return Ok(XqfRecord {
+        start : record[0].wrapping_sub(0x18).wrapping_sub(reader.keys.moveStart),
+        end : record[1].wrapping_sub(0x18).wrapping_sub(reader.keys.moveEnd),
+        hasNext,
+        hasVariation,
+        comment
+    });
+}
+
+///XQF text is GBK, as it came from Chinese versions of Windows. Plain ASCII reads the same either way.
+fn decodeText(bytes : &[u8]) -> String {
+    let (text, _) = encoding_rs::GBK.decode_without_bom_handling(bytes);
+    return text.trim_end_matches('\0').trim().to_string();
+}
+
+///Header text is stored Pascal-style: a length byte, then that many bytes.
+fn headerText(header : &[u8], offset : usize, size : usize) -> String {
+    let length = (header[offset] as usize).min(size - 1);
+    return decodeText(&header[offset + 1..offset + 1 + length]);
+}
+
+///XQF squares are x * 10 + y, with x and y the same way round as ours. Anything past the board means no square at all.
+fn square(xy : u8) -> Option<PackedCoord> {
+    if xy > 89 {
+        return None;
+    }
+    return Some(PackedCoord::new_from_usize((xy / 10) as usize, (xy % 10) as usize));
+}
+
+///Reads an XQF file, encrypted or not, checking that every move in it (variations included) is legal.
+pub fn readXqf(bytes : &[u8]) -> Result<XqfGame, XqfError> {
+    if !bytes.starts_with(b"XQ") {
+        return Err(XqfError::BadSignature);
+    }
+    if bytes.len() < HEADER_SIZE {
+        return Err(XqfError::Truncated);
+    }
+    let header = &bytes[..HEADER_SIZE];
+    let version = header[2];
+    let keys = XqfKeys::new(header);
+
+    // From 1.2 on, the pieces are rotated round as well as shifted
+    let mut pieces = [None;32];
+    for i in 0..32 {
+        let index = if version >= 12 { (i + keys.pieces as usize + 1) & 31 } else { i };
+        pieces[index] = square(header[16 + i].wrapping_sub(keys.pieces));
+    }
+
+    // The move tree is stored depth first: each move, then the line following it, then its alternatives
+    let mut reader = XqfReader { bytes, pos : HEADER_SIZE, keys : &keys };
+    let root = readRecord(&mut reader, version)?;
+    let mut nodes = vec![XqfNode { packedMove : PackedMove::new(), comment : root.comment, parent : None, children : Vec::new() }];
+    let mut squares = Vec::new();
+    let mut parent = if root.hasNext { Some(0) } else { None };
+    let mut pendingVariations = Vec::new(); // Nodes whose alternatives come once everything following them has been read
+    while let Some(parentIndex) = parent {
+        let record = readRecord(&mut reader, version)?;
+        let index = nodes.len();
+        squares.push((record.start, record.end));
+        nodes.push(XqfNode { packedMove : PackedMove::new(), comment : record.comment, parent : Some(parentIndex), children : Vec::new() });
+        nodes[parentIndex].children.push(index);
+        if record.hasVariation {
+            pendingVariations.push(index);
+        }
+        parent = if record.hasNext { Some(index) } else { pendingVariations.pop().and_then(|sibling| nodes[sibling].parent) };
+    }
+
+    // Whoever makes the first move is whoever's turn it is, which is more trustworthy than the header's say-so
+    let firstMover = squares.first().and_then(|&(start, _)| square(start));
+    let isRedTurn = match firstMover.and_then(|start| pieces.iter().position(|&coord| coord == Some(start))) {
+        Some(piece) => piece < 16,
+        None => header[50] == 0
+    };
+
+    let mut grid = [[None;9];10];
+    for (i, coord) in pieces.iter().enumerate() {
+        let Some(coord) = coord else {
+            continue; // Captured
+        };
+        let letter = PIECE_ORDER[i % 16] as char;
+        grid[coord.y()][coord.x()] = Some(if i < 16 { letter } else { letter.to_ascii_lowercase() });
+    }
+    let mut fen = String::new();
+    for (y, rank) in grid.iter().enumerate().rev() {
+        let mut empties = 0;
+        for tile in rank {
+            match tile {
+                Some(letter) => {
+                    if empties > 0 {
+                        fen.push_str(&empties.to_string());
+                        empties = 0;
+                    }
+                    fen.push(*letter);
+                }
+                None => empties += 1
+            }
+        }
+        if empties > 0 {
+            fen.push_str(&empties.to_string());
+        }
+        if y > 0 {
+            fen.push('/');
+        }
+    }
+    fen.push_str(if isRedTurn { " w" } else { " b" });
+    let startPosition = BoardState::try_from_fen(&fen).map_err(XqfError::BadPosition)?;
+
+    // Parents come before their children, so one pass is enough to check every move from the position it was played in
+    let mut positions = vec![startPosition.clone()];
+    let mut depths = vec![0];
+    for (i, &(start, end)) in squares.iter().enumerate() {
+        let index = i + 1;
+        let parentIndex = nodes[index].parent.expect("Only the root has no parent");
+        let parentPosition = &positions[parentIndex];
+        let packedMove = match (square(start), square(end)) {
+            (Some(start), Some(end)) => Some(PackedMove::new_from_packed(start, end)),
+            _ => None
+        }.filter(|packedMove| parentPosition.getLegalMoves().contains(packedMove))
+            .ok_or_else(|| XqfError::BadMove(depths[parentIndex], format!("{:02}-{:02}", start, end)))?;
+        nodes[index].packedMove = packedMove;
+        positions.push(parentPosition.branch(packedMove));
+        depths.push(depths[parentIndex] + 1);
+    }
+
+    let mut tags = Vec::new();
+    for (name, offset, size) in TEXT_FIELDS {
+        let text = headerText(header, offset, size);
+        if !text.is_empty() {
+            tags.push((name.to_string(), text));
+        }
+    }
+    let result = match header[51] {
+        1 => Some(GameResult::RedWins),
+        2 => Some(GameResult::BlackWins),
+        3 => Some(GameResult::Draw),
+        _ => None
+    };
+    return Ok(XqfGame { tags, result, startPosition, nodes });
+}
+
+impl XqfGame {
+    ///The main line, following the first child all the way down.
+    fn mainLine(&self) -> Vec<usize> {
+        let mut ret = vec![0];
+        while let Some(&child) = self.nodes[*ret.last().unwrap()].children.first() {
+            ret.push(child);
+        }
+        return ret;
+    }
+
+    ///The main line as a game, left at the end of it.
+    pub fn toGame(&self) -> Game {
+        let mut game = Game::new_from_position(self.startPosition.clone());
+        for index in self.mainLine().into_iter().skip(1) {
+            game.playMove(self.nodes[index].packedMove);
+        }
+        return game;
+    }
+
+    ///The main line and its comments, ready for writing out with pgn::writePgn.
+    pub fn toPgn(&self) -> PgnGame {
+        let mut pgnGame = PgnGame::new(self.toGame(), self.result);
+        for (name, value) in &self.tags {
+            match pgnGame.tags.iter_mut().find(|(tagName, _)| tagName == name) {
+                Some(tag) => tag.1 = value.clone(),
+                None => pgnGame.tags.push((name.clone(), value.clone()))
+            }
+        }
+        pgnGame.comments = self.mainLine().into_iter().enumerate()
+            .filter(|(_, index)| !self.nodes[*index].comment.is_empty())
+            .map(|(ply, index)| (ply, self.nodes[index].comment.clone()))
+            .collect();
+        return pgnGame;
+    }
+}